@@ -1,70 +1,181 @@
 use crate::bodies::*;
+use crate::bullet::{HitList, Owner};
+use crate::gravity::Swallowed;
 use crate::grid::*;
 use crate::load_spawner;
 use crate::schedule::InGameSet;
 use crate::score::Scored;
-use crate::spawner::SpawnGenerator;
+use crate::ship::{Shield, Ship};
+use crate::snapshot::{PendingSnapshot, RestoredEntities};
+use crate::spawner::{SpawnGenerator, SpawnPolicy};
+use crate::wave::Wave;
 use crate::GameState;
 use bevy::prelude::*;
 use bevy::render::mesh::{self, PrimitiveTopology};
 use bevy::render::render_asset::RenderAssetUsages;
-use bevy::time::common_conditions::on_timer;
 use bevy_common_assets::ron::RonAssetPlugin;
 use bevy_turborand::prelude::*;
 use serde::Deserialize;
-use std::time::Duration;
 //use web_sys::console;
 
 
-#[derive(Deserialize, Asset, Clone, Copy, TypePath)]
-struct AsteroidConfig {
+// what a destroyed asteroid breaks into
+#[derive(Deserialize, Clone, Copy, Debug)]
+struct SplitTier {
+    // applies to asteroids bigger than this
+    min_scale: f32,
+    children: u32,
+    // degrees between the outermost children, fanned out around the parent's heading
+    spread: f32,
+    // child scale relative to the parent
+    scale_ratio: f32,
+    // child speed relative to the parent
+    velocity: f32,
+    score: u16,
+}
+
+// damages every asteroid and ship within radius when the asteroid is destroyed
+#[derive(Deserialize, Clone, Copy, Debug)]
+struct Explosion {
+    radius: f32,
+    damage: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct AsteroidKindConfig {
+    // relative chance of a random spawn being this kind
+    weight: f32,
+    color: (f32, f32, f32),
+    density: f32,
+    // hit points per unit of scale, so bigger rocks take more hits
+    health: f32,
+    // largest first, the first tier an asteroid is bigger than decides how it splits
+    tiers: Vec<SplitTier>,
+    // for one too small for any tier, it goes without splitting
+    score: u16,
+    explosion: Option<Explosion>,
+}
+
+impl AsteroidKindConfig {
+    fn tier(&self, scale: f32) -> Option<&SplitTier> {
+        self.tiers.iter().find(|tier| scale > tier.min_scale)
+    }
+}
+
+#[derive(Deserialize, Asset, Clone, TypePath)]
+pub(crate) struct AsteroidConfig {
     varients: usize,
     num_verts: (usize, usize),
     angle_range: f32,
     radius_range: f32,
     radius_base: f32,
+    restitution: f32,
+    friction: f32,
+    spawn: SpawnPolicy,
+    kinds: Vec<AsteroidKindConfig>,
 }
 
 #[derive(Resource)]
-struct AsteroidConfigHandle {
+pub(crate) struct AsteroidConfigHandle {
     config: Handle<AsteroidConfig>,
 }
 
 #[derive(Resource)]
-struct AsteroidAssets {
+pub(crate) struct AsteroidAssets {
+    // outer vertex ring of each variant, used for the collider
+    outlines: Vec<Vec<Vec2>>,
+    // missing when running headless
+    render: Option<AsteroidRender>,
+}
+
+struct AsteroidRender {
     meshes: Vec<Handle<Mesh>>,
-    material: Handle<ColorMaterial>,
+    // one per kind
+    materials: Vec<Handle<ColorMaterial>>,
 }
 
 #[derive(Component)]
 pub struct Asteroid;
 
+// which of the loaded outlines the asteroid uses
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AsteroidVariant(pub usize);
+
+// index into the kinds of the asteroid config, children keep their parent's
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AsteroidKind(pub usize);
+
+// one more asteroid of the wave, it comes in from just offscreen
+#[derive(Event)]
+pub(crate) struct SpawnAsteroid {
+    pub(crate) scale: f32,
+    // scales the random drift it starts with
+    pub(crate) speed: f32,
+}
+
+// an explosive asteroid went off, ships in range are hit like by any other asteroid
+#[derive(Event)]
+pub(crate) struct Exploded {
+    pub(crate) position: Vec2,
+    pub(crate) radius: f32,
+}
+
 #[derive(Bundle)]
 struct AsteroidBundle {
     asteroid: Asteroid,
+    variant: AsteroidVariant,
+    kind: AsteroidKind,
     position: Position,
+    previous_position: PreviousPosition,
     velocity: Velocity,
     rotation: Rotation,
+    previous_rotation: PreviousRotation,
     angular_velocity: AngularVelocity,
     scale: Scale,
     rigid_body: RigidBody,
+    material: PhysicsMaterial,
+    polygon: PolygonCollider,
     collider: Collider,
+    health: Health,
 }
 
 impl AsteroidBundle {
-    fn new(position: Vec2, velocity: Vec2, angular_velocity: f32, scale: f32) -> Self {
+    fn new(
+        position: Vec2,
+        velocity: Vec2,
+        angular_velocity: f32,
+        scale: f32,
+        variant: usize,
+        outline: &[Vec2],
+        material: PhysicsMaterial,
+        kind: usize,
+        kind_config: &AsteroidKindConfig,
+    ) -> Self {
+        let polygon = PolygonCollider::new(outline, scale * 0.01);
         Self {
             asteroid: Asteroid,
+            variant: AsteroidVariant(variant),
+            kind: AsteroidKind(kind),
             position: Position(position),
+            previous_position: PreviousPosition(position),
             velocity: Velocity(velocity),
             scale: Scale(scale),
             rotation: Rotation(0.0),
+            previous_rotation: PreviousRotation(0.0),
             angular_velocity: AngularVelocity(angular_velocity),
+            // circle stays as the broadphase bound around the outline
             rigid_body: RigidBody {
-                radius: scale * 0.01,
-                mass: 2.0,
+                radius: polygon.bounding_radius(),
+                mass: polygon.area() * kind_config.density,
+            },
+            material,
+            polygon,
+            collider: Collider {
+                team: 0,
+                membership: LAYER_ASTEROID,
+                filter: LAYER_ASTEROID | LAYER_SHIP | LAYER_BULLET | LAYER_MISSILE,
             },
-            collider: Collider { team: 0 },
+            health: Health::full(scale * kind_config.health),
         }
     }
 }
@@ -81,8 +192,8 @@ fn load_config(
 
 fn load_asteroids(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
     mut spawner: ResMut<SpawnGenerator>,
     configs: Res<Assets<AsteroidConfig>>,
     config_handle: Res<AsteroidConfigHandle>,
@@ -92,16 +203,34 @@ fn load_asteroids(
         return;
     }
     if let Some(config) = configs.get(config_handle.config.id()) {
-        let material = materials.add(Color::srgb(0.5, 1., 0.5));
-
-        let mut new_meshes = Vec::with_capacity(config.varients);
+        let mut outlines = Vec::with_capacity(config.varients);
+        let mut rims = Vec::with_capacity(config.varients);
         for _ in 0..config.varients {
-            new_meshes.push(meshes.add(create_astroid_mesh(&mut spawner, &config)));
+            let outline = create_asteroid_outline(&mut spawner, config);
+            rims.push(create_asteroid_rim(&outline));
+            outlines.push(outline);
         }
 
+        // meshes don't touch the rng, so headless runs see the same rolls
+        let render = match (meshes, materials) {
+            (Some(mut meshes), Some(mut materials)) => Some(AsteroidRender {
+                meshes: outlines
+                    .iter()
+                    .zip(rims.iter())
+                    .map(|(outline, rim)| meshes.add(create_astroid_mesh(outline, rim)))
+                    .collect(),
+                materials: config
+                    .kinds
+                    .iter()
+                    .map(|kind| materials.add(Color::srgb(kind.color.0, kind.color.1, kind.color.2)))
+                    .collect(),
+            }),
+            _ => None,
+        };
+
         commands.insert_resource(AsteroidAssets {
-            meshes: new_meshes,
-            material,
+            outlines: rims,
+            render,
         });
     }
 }
@@ -111,46 +240,100 @@ fn spawn_asteroid(
     asteroid_assets: &Res<AsteroidAssets>,
     spawner: &mut ResMut<SpawnGenerator>,
     config: &AsteroidConfig,
+    kind: usize,
     position: Vec2,
     velocity: Vec2,
     angular_velocity: f32,
     scale: f32,
 ) {
     let mesh = spawner.rng.usize(0..config.varients);
-    commands.spawn((
-        AsteroidBundle::new(position, velocity, angular_velocity, scale),
-        Mesh2d(asteroid_assets.meshes[mesh].clone()),
-        MeshMaterial2d(asteroid_assets.material.clone()),
-        Transform::default(),
+    spawn_asteroid_variant(
+        commands,
+        asteroid_assets,
+        config,
+        kind,
+        mesh,
+        position,
+        velocity,
+        angular_velocity,
+        scale,
+    );
+}
+
+fn spawn_asteroid_variant<'a>(
+    commands: &'a mut Commands,
+    asteroid_assets: &AsteroidAssets,
+    config: &AsteroidConfig,
+    kind: usize,
+    mesh: usize,
+    position: Vec2,
+    velocity: Vec2,
+    angular_velocity: f32,
+    scale: f32,
+) -> EntityCommands<'a> {
+    let mut asteroid = commands.spawn(AsteroidBundle::new(
+        position,
+        velocity,
+        angular_velocity,
+        scale,
+        mesh,
+        &asteroid_assets.outlines[mesh],
+        PhysicsMaterial {
+            restitution: config.restitution,
+            friction: config.friction,
+        },
+        kind,
+        &config.kinds[kind],
     ));
+    if let Some(render) = &asteroid_assets.render {
+        asteroid.insert((
+            Mesh2d(render.meshes[mesh].clone()),
+            MeshMaterial2d(render.materials[kind].clone()),
+            Transform::default(),
+        ));
+    }
+    asteroid
 }
 
-fn spawn_asteroid_child(
+fn spawn_asteroid_children(
     commands: &mut Commands,
     asteroid_assets: &Res<AsteroidAssets>,
     spawner: &mut ResMut<SpawnGenerator>,
     config: &AsteroidConfig,
+    kind: usize,
+    tier: &SplitTier,
     position: Vec2,
     velocity: Vec2,
     scale: f32,
-    offset: f32,
 ) {
     let vel_len = velocity.length();
-    let vel_offset1 = Rot2::degrees(180.0-offset) * velocity.normalize();
-    let ang_vel = spawner.rng.f32_normalized();
-    spawn_asteroid(
-        commands,
-        asteroid_assets,
-        spawner,
-        config,
-        position + vel_offset1 * scale * 0.0005,
-        vel_offset1 * vel_len * -0.75,
-        ang_vel,
-        scale / 1.5,
-    );
+    for child in 0..tier.children {
+        let offset = if tier.children > 1 {
+            tier.spread * (child as f32 / (tier.children - 1) as f32 - 0.5)
+        } else {
+            0.0
+        };
+        // pushed back out of the parent's centre, against the direction it flies off in
+        let direction = Rot2::degrees(-offset) * velocity.normalize();
+        let ang_vel = spawner.rng.f32_normalized();
+        spawn_asteroid(
+            commands,
+            asteroid_assets,
+            spawner,
+            config,
+            kind,
+            position - direction * scale * 0.0005,
+            direction * vel_len * tier.velocity,
+            ang_vel,
+            scale * tier.scale_ratio,
+        );
+    }
 }
 
-fn create_astroid_mesh(spawner: &mut ResMut<SpawnGenerator>, config: &AsteroidConfig) -> Mesh {
+fn create_asteroid_outline(
+    spawner: &mut ResMut<SpawnGenerator>,
+    config: &AsteroidConfig,
+) -> Vec<Vec2> {
     let rng = &mut spawner.rng;
     // create semi-random circle
     let num_verts = rng.usize(config.num_verts.0..config.num_verts.1);
@@ -165,12 +348,19 @@ fn create_astroid_mesh(spawner: &mut ResMut<SpawnGenerator>, config: &AsteroidCo
         let point = rotator * Vec2::new(0.0, radius);
         positions.push(point)
     }
+    positions
+}
+
+// the mesh draws a band from the outline out to this ring, so the ring is what the rock looks
+// like from outside and what the collider has to follow
+fn create_asteroid_rim(outline: &[Vec2]) -> Vec<Vec2> {
+    let num_verts = outline.len();
 
     // calculate normals for inset
     // normals can face the wrong way if the verts are concave
     // therefore, base normal direction on angle step
     let mut normals = Vec::with_capacity(num_verts);
-    let mut cycle = positions.iter().cycle().take(positions.len() + 2);
+    let mut cycle = outline.iter().cycle().take(num_verts + 2);
 
     let mut previous_position = cycle.next().unwrap();
     let mut current_position = cycle.next().unwrap();
@@ -194,13 +384,18 @@ fn create_astroid_mesh(spawner: &mut ResMut<SpawnGenerator>, config: &AsteroidCo
     }
     normals.rotate_right(1);
 
-    // inset
-    let mut positions_inset = Vec::with_capacity(num_verts);
-    for i in 0..num_verts {
-        let new_position = positions[i] + (normals[i] * 0.2);
-        positions_inset.push(new_position);
-    }
-    positions.extend(positions_inset);
+    // pushed out along the normals
+    outline
+        .iter()
+        .zip(normals)
+        .map(|(position, normal)| *position + normal * 0.2)
+        .collect()
+}
+
+fn create_astroid_mesh(outline: &[Vec2], rim: &[Vec2]) -> Mesh {
+    let num_verts = outline.len();
+    let mut positions = outline.to_vec();
+    positions.extend_from_slice(rim);
     let positions_3d: Vec<Vec3> = positions.into_iter().map(|pos| pos.extend(0.0)).collect();
 
     // calculate triangle indices
@@ -230,137 +425,223 @@ fn create_astroid_mesh(spawner: &mut ResMut<SpawnGenerator>, config: &AsteroidCo
     .with_inserted_indices(mesh::Indices::U32(indices))
 }
 
-fn spawn_asteroid_random(
+// kept out of the ships' way by the spawn policy
+pub(crate) fn spawn_asteroid_random(
     mut commands: Commands,
     asteroid_assets: Res<AsteroidAssets>,
     mut spawner: ResMut<SpawnGenerator>,
+    mut events: EventReader<SpawnAsteroid>,
+    mut wave: ResMut<Wave>,
+    ships: Query<&Position, With<Ship>>,
     grid: Res<Grid>,
     configs: Res<Assets<AsteroidConfig>>,
     config_handle: Res<AsteroidConfigHandle>,
 ) {
-    // spawn position offscreen inside grid extents
-    let x_dist = spawner.rng.f32_normalized() * grid.extends;
-    let y_dist = spawner.rng.f32_normalized() * grid.extends;
-    let x = if x_dist < 0.0 {
-        x_dist - grid.width_half
-    } else {
-        x_dist + grid.width_half
-    };
-    let y = if y_dist < 0.0 {
-        y_dist - grid.height_half
-    } else {
-        y_dist + grid.height_half
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
     };
-    let position = Vec2::new(x, y);
+    let ships: Vec<Vec2> = ships.iter().map(|position| position.0).collect();
+    for event in events.read() {
+        // nowhere clear of the ships, the wave sends it again after its interval
+        let Some(position) = spawner.safe_position(&config.spawn, &grid, &ships) else {
+            wave.to_spawn += 1;
+            continue;
+        };
+        let velocity = spawner.drift(&config.spawn, &grid, position, &ships, 2.0 * event.speed);
+        let angular_velocity = spawner.rng.f32_normalized() * 1.0;
 
-    let velocity = Vec2::new(
-        spawner.rng.f32_normalized() * 2.0,
-        spawner.rng.f32_normalized() * 2.0,
-    );
-    let scale = spawner.rng.f32() * 5.0 + 45.0;
-    let angular_velocity = spawner.rng.f32_normalized() * 1.0;
-
-    if let Some(config) = configs.get(config_handle.config.id()) {
+        let weights: Vec<f32> = config.kinds.iter().map(|kind| kind.weight).collect();
+        let kind = spawner.weighted(&weights);
         spawn_asteroid(
             &mut commands,
             &asteroid_assets,
             &mut spawner,
-            &config,
+            config,
+            kind,
             position,
             velocity,
             angular_velocity,
-            scale,
+            event.scale,
         );
     }
 }
 
+type Target = (
+    Entity,
+    &'static Collider,
+    &'static Position,
+    &'static Velocity,
+    &'static Scale,
+    &'static RigidBody,
+    &'static AsteroidKind,
+    &'static mut Health,
+);
+
+type Attacker = (
+    &'static Collider,
+    Option<&'static Damage>,
+    Option<&'static Owner>,
+    Option<&'static Shield>,
+    Option<&'static HitList>,
+);
+
 // TODO! switch spawning children to an event
-fn destroy_asteroids(
+// hits take health off, the asteroid only splits once it runs out.
+// explosions queue more hits, so one blast can set off the next within the same tick.
+// a raised shield bounces the asteroid off instead
+pub(crate) fn destroy_asteroids(
     mut commands: Commands,
     asteroid_assets: Option<Res<AsteroidAssets>>,
     mut spawner: ResMut<SpawnGenerator>,
-    asteroids: Query<(Entity, &Collider, &Position, &Velocity, &Scale), With<Asteroid>>,
-    colliders: Query<&Collider>,
+    mut asteroids: Query<Target, With<Asteroid>>,
+    colliders: Query<Attacker>,
     mut collisions: EventReader<Collision>,
     mut score: EventWriter<Scored>,
+    mut damaged: EventWriter<Damaged>,
+    mut exploded: EventWriter<Exploded>,
+    grid: Res<Grid>,
     configs: Res<Assets<AsteroidConfig>>,
     config_handle: Res<AsteroidConfigHandle>,
 ) {
     if let Some(config) = configs.get(config_handle.config.id()) {
         if let Some(assets) = asteroid_assets {
+            // (victim, attacker, damage)
+            let mut hits = Vec::new();
             for event in collisions.read() {
                 for (entity_a, entity_b) in [
                     (event.entity1, event.entity2),
                     (event.entity2, event.entity1),
                 ] {
-                    if let Ok((ast_entity, ast_collider, ast_pos, ast_vel, ast_scale)) =
-                        asteroids.get(entity_a)
+                    if let (
+                        Ok((_, ast_collider, ..)),
+                        Ok((collider, damage, owner, shield, hit_list)),
+                    ) = (asteroids.get(entity_a), colliders.get(entity_b))
                     {
-                        if let Ok(collider) = colliders.get(entity_b) {
-                            if collider.team != ast_collider.team {
-                                // TODO! add teams to score
-                                score.send(Scored);
-                                if ast_scale.0 > 25.0 {
-                                    spawn_asteroid_child(
-                                        &mut commands,
-                                        &assets,
-                                        &mut spawner,
-                                        &config,
-                                        ast_pos.0,
-                                        ast_vel.0,
-                                        ast_scale.0,
-                                        50.0,
-                                    );
-                                    spawn_asteroid_child(
-                                        &mut commands,
-                                        &assets,
-                                        &mut spawner,
-                                        &config,
-                                        ast_pos.0,
-                                        ast_vel.0,
-                                        ast_scale.0,
-                                        -50.0,
-                                    );
-                                }
-                                commands.entity(ast_entity).despawn();
-                            }
+                        // a piercing bullet only hurts each asteroid once
+                        if collider.team != ast_collider.team
+                            && !shield.is_some_and(|shield| shield.up)
+                            && !hit_list.is_some_and(|hits| hits.0.contains(&entity_a))
+                        {
+                            hits.push((
+                                entity_a,
+                                owner.map_or(entity_b, |owner| owner.shooter),
+                                damage.map_or(0.0, |damage| damage.0),
+                            ));
                         }
                     }
                 }
             }
+
+            // a spread can land several hits on one asteroid in a tick, it only splits once
+            let mut destroyed = Vec::new();
+            let mut next = 0;
+            while let Some((victim, attacker, amount)) = hits.get(next).copied() {
+                next += 1;
+                if destroyed.contains(&victim) {
+                    continue;
+                }
+                let Ok((_, _, ast_pos, ast_vel, ast_scale, ast_body, kind, mut health)) =
+                    asteroids.get_mut(victim)
+                else {
+                    continue;
+                };
+                health.current -= amount;
+                let fatal = health.current <= 0.0;
+                damaged.send(Damaged {
+                    attacker,
+                    victim,
+                    amount,
+                    fatal,
+                });
+                if !fatal {
+                    continue;
+                }
+                destroyed.push(victim);
+                let (position, velocity, scale, radius) =
+                    (ast_pos.0, ast_vel.0, ast_scale.0, ast_body.radius);
+                let kind = kind.0;
+                let kind_config = &config.kinds[kind];
+                let tier = kind_config.tier(scale);
+                // TODO! add teams to score
+                score.send(Scored {
+                    points: tier.map_or(kind_config.score, |tier| tier.score),
+                });
+                if let Some(tier) = tier {
+                    spawn_asteroid_children(
+                        &mut commands,
+                        &assets,
+                        &mut spawner,
+                        config,
+                        kind,
+                        tier,
+                        position,
+                        velocity,
+                        scale,
+                    );
+                }
+                if let Some(explosion) = kind_config.explosion {
+                    let radius = radius + explosion.radius;
+                    exploded.send(Exploded { position, radius });
+                    for (other, _, other_pos, _, _, other_body, ..) in asteroids.iter() {
+                        if other != victim
+                            && grid.wrapped_delta(position, other_pos.0).length()
+                                < radius + other_body.radius
+                        {
+                            hits.push((other, attacker, explosion.damage));
+                        }
+                    }
+                }
+                commands.entity(victim).despawn();
+            }
         }
     }
 }
 
-fn bounce_asteroids(
-    mut asteroids: Query<(&mut Position, &mut Velocity, &RigidBody), With<Asteroid>>,
+pub fn asteroids_loaded(asteroid_assets: Option<Res<AsteroidAssets>>) -> bool {
+    asteroid_assets.is_some()
+}
+
+pub(crate) fn bounce_asteroids(
+    mut asteroids: Query<
+        (
+            &mut Position,
+            &mut Velocity,
+            &mut AngularVelocity,
+            &RigidBody,
+            &PhysicsMaterial,
+        ),
+        With<Asteroid>,
+    >,
     mut collisions: EventReader<Collision>,
 ) {
     for event in collisions.read() {
         if let Ok(
-            [(mut ast_a_pos, mut ast_a_vel, ast_a_body), (mut ast_b_pos, mut ast_b_vel, ast_b_body)],
+            [(mut ast_a_pos, mut ast_a_vel, mut ast_a_ang, ast_a_body, ast_a_mat), (mut ast_b_pos, mut ast_b_vel, mut ast_b_ang, ast_b_body, ast_b_mat)],
         ) = asteroids.get_many_mut([event.entity1, event.entity2])
         {
             //console::log_1(&"Received CollisionEvent".into());
 
-            /*console::log_1(&format!(
-                "Before Collision: A Vel: {:?}, B Vel: {:?}",
-                ast_a_vel.0, ast_b_vel.0
-            )
-            .into());*/
             let normal = event.dir.normalize();
-            (ast_a_vel.0, ast_b_vel.0) = collision_bounce(
-                ast_a_vel.0,
-                ast_b_vel.0,
+            // contact point sits on each rim along the normal
+            ((ast_a_vel.0, ast_a_ang.0), (ast_b_vel.0, ast_b_ang.0)) = collision_bounce(
+                &ImpactBody {
+                    velocity: ast_a_vel.0,
+                    angular_velocity: ast_a_ang.0,
+                    mass: ast_a_body.mass,
+                    inertia: ast_a_body.inertia(),
+                    offset: normal * ast_a_body.radius,
+                    material: *ast_a_mat,
+                },
+                &ImpactBody {
+                    velocity: ast_b_vel.0,
+                    angular_velocity: ast_b_ang.0,
+                    mass: ast_b_body.mass,
+                    inertia: ast_b_body.inertia(),
+                    offset: -normal * ast_b_body.radius,
+                    material: *ast_b_mat,
+                },
                 normal,
-                ast_a_body.mass,
-                ast_b_body.mass,
             );
-            /*console::log_1(&format!(
-                "After Collision: A Vel: {:?}, B Vel: {:?}",
-                ast_a_vel.0, ast_b_vel.0
-            )
-            .into());*/
 
             let depth = event.collide_dist - event.dist;
             let correction = normal * (depth * 0.8);
@@ -370,6 +651,65 @@ fn bounce_asteroids(
     }
 }
 
+// outlines come from the world seed, so the saved variant index picks the same shape again
+pub(crate) fn restore_asteroids(
+    mut commands: Commands,
+    pending: Res<PendingSnapshot>,
+    mut restored: ResMut<RestoredEntities>,
+    asteroid_assets: Res<AsteroidAssets>,
+    asteroids: Query<Entity, With<Asteroid>>,
+    configs: Res<Assets<AsteroidConfig>>,
+    config_handle: Res<AsteroidConfigHandle>,
+) {
+    if let Some(config) = configs.get(config_handle.config.id()) {
+        for entity in asteroids.iter() {
+            commands.entity(entity).despawn();
+        }
+        restored.asteroids.clear();
+        for saved in pending.0.asteroids.iter() {
+            if saved.variant >= asteroid_assets.outlines.len() {
+                warn!("snapshot asteroid uses missing variant {}", saved.variant);
+                restored.asteroids.push(None);
+                continue;
+            }
+            let Some(kind) = config.kinds.get(saved.kind) else {
+                warn!("snapshot asteroid uses missing kind {}", saved.kind);
+                restored.asteroids.push(None);
+                continue;
+            };
+            let mut asteroid = spawn_asteroid_variant(
+                &mut commands,
+                &asteroid_assets,
+                config,
+                saved.kind,
+                saved.variant,
+                saved.body.position.0,
+                saved.body.velocity.0,
+                saved.body.angular_velocity.0,
+                saved.body.scale.0,
+            );
+            saved.body.restore(&mut asteroid);
+            asteroid.insert(Health {
+                current: saved.health,
+                max: saved.body.scale.0 * kind.health,
+            });
+            restored.asteroids.push(Some(asteroid.id()));
+        }
+    }
+}
+
+fn swallowed_asteroids(
+    mut commands: Commands,
+    asteroids: Query<Entity, With<Asteroid>>,
+    mut events: EventReader<Swallowed>,
+) {
+    for event in events.read() {
+        if let Ok(asteroid) = asteroids.get(event.entity) {
+            commands.entity(asteroid).despawn();
+        }
+    }
+}
+
 fn despawn_asteroids(mut commands: Commands, asteroids: Query<Entity, With<Asteroid>>) {
     for entity in asteroids.iter() {
         commands.entity(entity).despawn();
@@ -381,20 +721,396 @@ pub struct AsteroidsPlugin;
 impl Plugin for AsteroidsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(RonAssetPlugin::<AsteroidConfig>::new(&["ast.ron"]));
+        app.add_event::<SpawnAsteroid>();
+        app.add_event::<Exploded>();
         app.add_systems(Startup, (load_spawner, load_config));
         app.add_systems(Update, (load_asteroids).in_set(InGameSet::LoadEntities));
         app.add_systems(
             Update,
-            (destroy_asteroids).in_set(InGameSet::DespawnEntities),
+            (restore_asteroids)
+                .in_set(InGameSet::LoadEntities)
+                .run_if(resource_exists::<PendingSnapshot>),
         );
         app.add_systems(
-            Update,
-            (
-                spawn_asteroid_random.run_if(on_timer(Duration::from_secs(2))),
-            )
-                .in_set(InGameSet::UpdateEntities),
+            FixedUpdate,
+            (destroy_asteroids, swallowed_asteroids)
+                .chain()
+                .in_set(InGameSet::DespawnEntities),
+        );
+        // spawned at the end of the tick like bullets, otherwise whether a new asteroid
+        // moves on its first tick depends on where the command flush lands
+        app.add_systems(
+            FixedUpdate,
+            (bounce_asteroids, spawn_asteroid_random).in_set(InGameSet::CollisionReaction),
         );
-        app.add_systems(Update, (bounce_asteroids).in_set(InGameSet::CollisionReaction));
         app.add_systems(OnEnter(GameState::GameOver), despawn_asteroids);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(tiers: Vec<SplitTier>, explosion: Option<Explosion>) -> AsteroidKindConfig {
+        AsteroidKindConfig {
+            weight: 1.0,
+            color: (1.0, 1.0, 1.0),
+            density: 1.0,
+            health: 10.0,
+            tiers,
+            score: 5,
+            explosion,
+        }
+    }
+
+    fn tier(min_scale: f32, children: u32, score: u16) -> SplitTier {
+        SplitTier {
+            min_scale,
+            children,
+            spread: 90.0,
+            scale_ratio: 0.5,
+            velocity: 1.0,
+            score,
+        }
+    }
+
+    fn test_config(kinds: Vec<AsteroidKindConfig>) -> AsteroidConfig {
+        AsteroidConfig {
+            varients: 1,
+            num_verts: (8, 9),
+            angle_range: 0.0,
+            radius_range: 0.0,
+            radius_base: 1.0,
+            restitution: 1.0,
+            friction: 0.0,
+            spawn: SpawnPolicy {
+                safe_radius: 1.0,
+                tangential_bias: 0.0,
+                attempts: 4,
+            },
+            kinds,
+        }
+    }
+
+    // a unit octagon, at scale 40 the rock is 0.4 across the middle
+    fn octagon() -> Vec<Vec2> {
+        (0..8)
+            .map(|i| Rot2::degrees(45.0 * i as f32) * Vec2::Y)
+            .collect()
+    }
+
+    fn test_app(config: &AsteroidConfig) -> App {
+        let mut app = App::new();
+        app.add_event::<Collision>();
+        app.add_event::<Scored>();
+        app.add_event::<Damaged>();
+        app.add_event::<Exploded>();
+        let mut configs = Assets::<AsteroidConfig>::default();
+        let handle = configs.add(config.clone());
+        app.insert_resource(configs);
+        app.insert_resource(AsteroidConfigHandle { config: handle });
+        app.insert_resource(AsteroidAssets {
+            outlines: vec![octagon()],
+            render: None,
+        });
+        app.insert_resource(SpawnGenerator {
+            rng: RngComponent::with_seed(1),
+        });
+        app.insert_resource(Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        });
+        app.add_systems(Update, destroy_asteroids);
+        app
+    }
+
+    fn spawn_rock(
+        app: &mut App,
+        config: &AsteroidConfig,
+        kind: usize,
+        position: Vec2,
+        scale: f32,
+    ) -> Entity {
+        let bundle = AsteroidBundle::new(
+            position,
+            Vec2::X,
+            0.0,
+            scale,
+            0,
+            &octagon(),
+            PhysicsMaterial {
+                restitution: config.restitution,
+                friction: config.friction,
+            },
+            kind,
+            &config.kinds[kind],
+        );
+        app.world_mut().spawn(bundle).id()
+    }
+
+    fn spawn_bullet(app: &mut App, damage: f32) -> Entity {
+        let collider = Collider {
+            team: 1,
+            membership: LAYER_BULLET,
+            filter: LAYER_ASTEROID,
+        };
+        app.world_mut().spawn((collider, Damage(damage))).id()
+    }
+
+    fn hit(app: &mut App, asteroid: Entity, attacker: Entity) {
+        app.world_mut().send_event(Collision {
+            entity1: attacker,
+            entity2: asteroid,
+            dir: Vec2::X,
+            dist: 0.0,
+            collide_dist: 0.1,
+        });
+        app.update();
+    }
+
+    fn events<E: Event + Clone>(app: &App) -> Vec<E> {
+        app.world()
+            .resource::<Events<E>>()
+            .iter_current_update_events()
+            .cloned()
+            .collect()
+    }
+
+    fn asteroid_count(app: &mut App) -> usize {
+        let world = app.world_mut();
+        world.query_filtered::<(), With<Asteroid>>().iter(world).count()
+    }
+
+    #[test]
+    fn hits_wear_health_down_until_it_runs_out() {
+        let config = test_config(vec![kind(Vec::new(), None)]);
+        let mut app = test_app(&config);
+        // 40 scale at 10 health per unit
+        let rock = spawn_rock(&mut app, &config, 0, Vec2::ZERO, 40.0);
+        let bullet = spawn_bullet(&mut app, 150.0);
+
+        hit(&mut app, rock, bullet);
+        let health = app.world().get::<Health>(rock).unwrap();
+        assert_eq!((health.current, health.max), (250.0, 400.0));
+        let damaged = events::<Damaged>(&app);
+        assert_eq!(damaged.len(), 1);
+        assert_eq!(
+            (damaged[0].victim, damaged[0].attacker, damaged[0].amount, damaged[0].fatal),
+            (rock, bullet, 150.0, false)
+        );
+
+        hit(&mut app, rock, bullet);
+        assert!(!events::<Damaged>(&app)[0].fatal);
+        hit(&mut app, rock, bullet);
+        assert!(events::<Damaged>(&app)[0].fatal);
+        assert!(app.world().get_entity(rock).is_err());
+    }
+
+    #[test]
+    fn credit_goes_to_the_shooter() {
+        let config = test_config(vec![kind(Vec::new(), None)]);
+        let mut app = test_app(&config);
+        let rock = spawn_rock(&mut app, &config, 0, Vec2::ZERO, 40.0);
+        let ship = app.world_mut().spawn_empty().id();
+        let bullet = spawn_bullet(&mut app, 1.0);
+        app.world_mut()
+            .entity_mut(bullet)
+            .insert(Owner { shooter: ship });
+
+        hit(&mut app, rock, bullet);
+        assert_eq!(events::<Damaged>(&app)[0].attacker, ship);
+    }
+
+    #[test]
+    fn same_team_and_repeat_hits_do_nothing() {
+        let config = test_config(vec![kind(Vec::new(), None)]);
+        let mut app = test_app(&config);
+        let rock = spawn_rock(&mut app, &config, 0, Vec2::ZERO, 40.0);
+        let other = spawn_rock(&mut app, &config, 0, Vec2::ONE, 40.0);
+        hit(&mut app, rock, other);
+        // a piercing bullet still inside the rock it went through
+        let bullet = spawn_bullet(&mut app, 1.0);
+        app.world_mut()
+            .entity_mut(bullet)
+            .insert(HitList(vec![rock]));
+        hit(&mut app, rock, bullet);
+
+        assert!(events::<Damaged>(&app).is_empty());
+        assert_eq!(app.world().get::<Health>(rock).unwrap().current, 400.0);
+    }
+
+    fn scored(app: &App) -> Vec<u16> {
+        app.world()
+            .resource::<Events<Scored>>()
+            .iter_current_update_events()
+            .map(|event| event.points)
+            .collect()
+    }
+
+    fn scales(app: &mut App) -> Vec<f32> {
+        let world = app.world_mut();
+        world
+            .query_filtered::<&Scale, With<Asteroid>>()
+            .iter(world)
+            .map(|scale| scale.0)
+            .collect()
+    }
+
+    // the first tier the rock is bigger than decides the split and the score
+    #[test]
+    fn splits_by_tier() {
+        let tiers = vec![tier(30.0, 3, 20), tier(15.0, 2, 50)];
+        let config = test_config(vec![kind(tiers, None)]);
+        let mut app = test_app(&config);
+        let rock = spawn_rock(&mut app, &config, 0, Vec2::ZERO, 40.0);
+        let bullet = spawn_bullet(&mut app, 1000.0);
+
+        hit(&mut app, rock, bullet);
+        assert_eq!(scored(&app), vec![20]);
+        assert_eq!(scales(&mut app), vec![20.0; 3]);
+
+        let world = app.world_mut();
+        let child = world
+            .query_filtered::<Entity, With<Asteroid>>()
+            .iter(world)
+            .next()
+            .unwrap();
+        hit(&mut app, child, bullet);
+        assert_eq!(scored(&app), vec![50]);
+        let mut left = scales(&mut app);
+        left.sort_by(f32::total_cmp);
+        assert_eq!(left, vec![10.0, 10.0, 20.0, 20.0]);
+
+        // below the last tier, gone without a split for the kind's own score
+        let world = app.world_mut();
+        let smallest = world
+            .query_filtered::<(Entity, &Scale), With<Asteroid>>()
+            .iter(world)
+            .find(|(_, scale)| scale.0 == 10.0)
+            .unwrap()
+            .0;
+        hit(&mut app, smallest, bullet);
+        assert_eq!(scored(&app), vec![5]);
+        assert_eq!(asteroid_count(&mut app), 3);
+    }
+
+    // a spread landing twice in one tick still splits the rock once
+    #[test]
+    fn splits_once_per_tick() {
+        let config = test_config(vec![kind(vec![tier(30.0, 2, 20)], None)]);
+        let mut app = test_app(&config);
+        let rock = spawn_rock(&mut app, &config, 0, Vec2::ZERO, 40.0);
+        for _ in 0..2 {
+            let bullet = spawn_bullet(&mut app, 1000.0);
+            app.world_mut().send_event(Collision {
+                entity1: bullet,
+                entity2: rock,
+                dir: Vec2::X,
+                dist: 0.0,
+                collide_dist: 0.1,
+            });
+        }
+        app.update();
+        assert_eq!(scored(&app), vec![20]);
+        assert_eq!(asteroid_count(&mut app), 2);
+    }
+
+    fn kinds(app: &mut App) -> Vec<usize> {
+        let world = app.world_mut();
+        world
+            .query_filtered::<&AsteroidKind, With<Asteroid>>()
+            .iter(world)
+            .map(|kind| kind.0)
+            .collect()
+    }
+
+    #[test]
+    fn picks_kinds_by_weight() {
+        let weighted = |weight| AsteroidKindConfig {
+            weight,
+            ..kind(Vec::new(), None)
+        };
+        let config = test_config(vec![weighted(0.0), weighted(3.0), weighted(1.0)]);
+        let mut app = test_app(&config);
+        app.add_event::<SpawnAsteroid>();
+        app.init_resource::<Wave>();
+        app.add_systems(Update, spawn_asteroid_random);
+        for _ in 0..400 {
+            app.world_mut().send_event(SpawnAsteroid {
+                scale: 40.0,
+                speed: 1.0,
+            });
+        }
+        app.update();
+
+        let kinds = kinds(&mut app);
+        let count = |kind| kinds.iter().filter(|&&k| k == kind).count();
+        assert_eq!(kinds.len(), 400);
+        assert_eq!(count(0), 0);
+        // 3 to 1, give or take the luck of the rolls
+        assert!(count(1) > count(2) * 2, "{} vs {}", count(1), count(2));
+        assert!(count(2) > 0);
+    }
+
+    // each blast reaches the next rock in the row, which goes off in the same tick
+    #[test]
+    fn explosions_set_each_other_off() {
+        let explosion = Explosion {
+            radius: 1.0,
+            damage: 1000.0,
+        };
+        let config = test_config(vec![kind(Vec::new(), Some(explosion)), kind(Vec::new(), None)]);
+        let mut app = test_app(&config);
+        let first = spawn_rock(&mut app, &config, 0, Vec2::new(-4.0, 0.0), 40.0);
+        spawn_rock(&mut app, &config, 0, Vec2::new(-2.5, 0.0), 40.0);
+        spawn_rock(&mut app, &config, 0, Vec2::new(-1.0, 0.0), 40.0);
+        // caught by the last blast but doesn't explode itself
+        let inert = spawn_rock(&mut app, &config, 1, Vec2::new(0.5, 0.0), 40.0);
+        // out of reach of every blast
+        let far = spawn_rock(&mut app, &config, 0, Vec2::new(4.0, 0.0), 40.0);
+        let bullet = spawn_bullet(&mut app, 1000.0);
+
+        hit(&mut app, first, bullet);
+        assert_eq!(
+            app.world().resource::<Events<Exploded>>().iter_current_update_events().count(),
+            3
+        );
+        let damaged = events::<Damaged>(&app);
+        assert_eq!(damaged.len(), 4);
+        // the chain is credited to whoever started it
+        assert!(damaged.iter().all(|event| event.attacker == bullet && event.fatal));
+        assert!(app.world().get_entity(inert).is_err());
+        assert_eq!(kinds(&mut app), vec![0]);
+        assert!(app.world().get_entity(far).is_ok());
+    }
+
+    // a ship parked so nothing offscreen is clear of it, the wave has to try again later
+    #[test]
+    fn spawns_wait_for_a_safe_spot() {
+        let mut config = test_config(vec![kind(Vec::new(), None)]);
+        config.spawn.safe_radius = 20.0;
+        let mut app = test_app(&config);
+        app.add_event::<SpawnAsteroid>();
+        app.init_resource::<Wave>();
+        app.add_systems(Update, spawn_asteroid_random);
+        let ship = app.world_mut().spawn((Ship, Position(Vec2::ZERO))).id();
+        app.world_mut().send_event(SpawnAsteroid {
+            scale: 40.0,
+            speed: 1.0,
+        });
+        app.update();
+        assert_eq!(asteroid_count(&mut app), 0);
+        assert_eq!(app.world().resource::<Wave>().to_spawn, 1);
+
+        app.world_mut().despawn(ship);
+        app.world_mut().send_event(SpawnAsteroid {
+            scale: 40.0,
+            speed: 1.0,
+        });
+        app.update();
+        assert_eq!(asteroid_count(&mut app), 1);
+    }
+}
+
@@ -1,26 +1,36 @@
+use crate::broadphase::SpatialHash;
+use crate::grid::{wrap_obj, Grid};
 use crate::schedule::InGameSet;
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 //use web_sys::console;
 
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub struct TimeStamp(pub Duration);
 
 // don't use Rot2 as it is effectively a 2d quat. 2d rots don't suffer from gimbal lock, so we don't need that complexity.
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Rotation(pub f32);
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct AngularVelocity(pub f32);
 
 #[derive(Component)]
 pub struct AngularAcceleration(pub f32);
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Position(pub Vec2);
 
+// last simulated state, so rendering can interpolate between fixed ticks
 #[derive(Component)]
+pub struct PreviousPosition(pub Vec2);
+
+#[derive(Component)]
+pub struct PreviousRotation(pub f32);
+
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Velocity(pub Vec2);
 
 #[derive(Component)]
@@ -32,9 +42,13 @@ pub struct Damping(pub f32);
 #[derive(Component)]
 pub struct AngularDamping(pub f32);
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Scale(pub f32);
 
+// swept collision tests for bodies that can cover more than their size in a single tick
+#[derive(Component)]
+pub struct FastMoving;
+
 #[derive(Component)]
 pub struct NeedsMesh;
 
@@ -44,44 +58,234 @@ pub struct NeedsConfig;
 #[derive(Component)]
 pub struct NeedsMaterial;
 
-#[derive(Component)]
+// world space force gathered over a tick, applied and cleared before velocity integration
+#[derive(Component, Default)]
+pub struct Force(pub Vec2);
+
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+#[require(Force)]
 pub struct RigidBody {
     pub radius: f32,
     pub mass: f32,
 }
 
+// treat bodies as solid discs
+impl RigidBody {
+    pub fn from_density(radius: f32, density: f32) -> Self {
+        Self {
+            radius,
+            mass: density * std::f32::consts::PI * radius * radius,
+        }
+    }
+
+    pub fn inertia(&self) -> f32 {
+        0.5 * self.mass * self.radius * self.radius
+    }
+}
+
+// bodies without a material bounce perfectly and slide without friction
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PhysicsMaterial {
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl Default for PhysicsMaterial {
+    fn default() -> Self {
+        Self {
+            restitution: 1.0,
+            friction: 0.0,
+        }
+    }
+}
+
+pub struct ImpactBody {
+    pub velocity: Vec2,
+    pub angular_velocity: f32,
+    pub mass: f32,
+    pub inertia: f32,
+    // contact point relative to the centre of the body
+    pub offset: Vec2,
+    pub material: PhysicsMaterial,
+}
+
+impl ImpactBody {
+    fn contact_velocity(&self) -> Vec2 {
+        self.velocity + self.angular_velocity * self.offset.perp()
+    }
+
+    // resistance to an impulse along dir at the contact point
+    fn inverse_mass_along(&self, dir: Vec2) -> f32 {
+        let arm = self.offset.perp_dot(dir);
+        1.0 / self.mass + arm * arm / self.inertia
+    }
+
+    fn apply(&self, impulse: Vec2) -> (Vec2, f32) {
+        (
+            self.velocity + impulse / self.mass,
+            self.angular_velocity + self.offset.perp_dot(impulse) / self.inertia,
+        )
+    }
+}
+
+// 2d impulse resolution, normal points from body1 to body2.
+// returns the new (velocity, angular velocity) of both bodies.
+// with restitution 1, friction 0 this is the plain elastic exchange along the normal
 pub fn collision_bounce(
-    vel1: Vec2,
-    vel2: Vec2,
+    body1: &ImpactBody,
+    body2: &ImpactBody,
     normal: Vec2,
-    mass1: f32,
-    mass2: f32,
-) -> (Vec2, Vec2) {
-    let tangent = Vec2::new(-normal.y, normal.x);
+) -> ((Vec2, f32), (Vec2, f32)) {
+    let relative = body2.contact_velocity() - body1.contact_velocity();
+    let relative_normal = relative.dot(normal);
+    // already separating, leave them be
+    if relative_normal > 0.0 {
+        return (
+            (body1.velocity, body1.angular_velocity),
+            (body2.velocity, body2.angular_velocity),
+        );
+    }
 
-    let vel1_normal = vel1.dot(normal);
-    let vel1_tangent = vel1.dot(tangent);
-    let vel2_normal = vel2.dot(normal);
-    let vel2_tangent = vel2.dot(tangent);
+    // the less bouncy of the two decides, a dead body soaks up a bouncy one
+    let restitution = body1.material.restitution.min(body2.material.restitution);
+    let friction = (body1.material.friction * body2.material.friction).sqrt();
 
-    let vel1_normal_new =
-        (vel1_normal * (mass1 - mass2) + 2.0 * mass2 * vel2_normal) / (mass1 + mass2);
-    let vel2_normal_new =
-        (vel2_normal * (mass2 - mass1) + 2.0 * mass1 * vel1_normal) / (mass1 + mass2);
+    let normal_impulse = -(1.0 + restitution) * relative_normal
+        / (body1.inverse_mass_along(normal) + body2.inverse_mass_along(normal));
 
-    (
-        (tangent * vel1_tangent) + (normal * vel1_normal_new),
-        (tangent * vel2_tangent) + (normal * vel2_normal_new),
-    )
+    // coulomb friction, can't exceed the normal impulse scaled by the friction coefficient
+    let tangent = normal.perp();
+    let tangent_impulse = (-relative.dot(tangent)
+        / (body1.inverse_mass_along(tangent) + body2.inverse_mass_along(tangent)))
+    .clamp(-friction * normal_impulse, friction * normal_impulse);
+
+    let impulse = normal * normal_impulse + tangent * tangent_impulse;
+    (body1.apply(-impulse), body2.apply(impulse))
 }
 
-fn collide(pos1: Vec2, pos2: Vec2, r1: f32, r2: f32) -> (Vec2, f32, f32) {
-    let dir = pos2 - pos1;
+// direction and distance are measured across the screen wrap
+pub fn collide(pos1: Vec2, pos2: Vec2, r1: f32, r2: f32, grid: &Grid) -> (Vec2, f32, f32) {
+    let dir = grid.wrapped_delta(pos1, pos2);
     let dist = dir.length().abs();
     let collide_dist = r1 + r2;
     (dir, dist, collide_dist)
 }
 
+// outline in world units around the body's position, unrotated. may be concave
+#[derive(Component, Clone)]
+pub struct PolygonCollider {
+    pub vertices: Vec<Vec2>,
+}
+
+impl PolygonCollider {
+    pub fn new(outline: &[Vec2], scale: f32) -> Self {
+        Self {
+            vertices: outline.iter().map(|vertex| *vertex * scale).collect(),
+        }
+    }
+
+    // shoelace formula, absolute so winding order doesn't matter
+    pub fn area(&self) -> f32 {
+        edges(&self.vertices)
+            .fold(0.0, |sum, (a, b)| sum + a.perp_dot(b))
+            .abs()
+            * 0.5
+    }
+
+    pub fn bounding_radius(&self) -> f32 {
+        self.vertices
+            .iter()
+            .fold(0.0, |max, vertex| max.max(vertex.length()))
+    }
+
+    fn placed(&self, position: Vec2, rotation: f32) -> Vec<Vec2> {
+        let rotator = Rot2::radians(rotation);
+        self.vertices
+            .iter()
+            .map(|vertex| position + rotator * *vertex)
+            .collect()
+    }
+}
+
+fn edges(polygon: &[Vec2]) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+    polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| (*a, *b))
+}
+
+// even-odd rule, so it holds for concave outlines
+fn point_in_polygon(point: Vec2, polygon: &[Vec2]) -> bool {
+    let mut inside = false;
+    for (a, b) in edges(polygon) {
+        if (a.y > point.y) != (b.y > point.y) {
+            let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn closest_point_on_segment(point: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let edge = b - a;
+    if edge.length_squared() == 0.0 {
+        return a;
+    }
+    let t = ((point - a).dot(edge) / edge.length_squared()).clamp(0.0, 1.0);
+    a + edge * t
+}
+
+fn point_segment_distance(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    point.distance(closest_point_on_segment(point, a, b))
+}
+
+fn segments_intersect(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> bool {
+    let da = a2 - a1;
+    let db = b2 - b1;
+    let denom = da.perp_dot(db);
+    if denom == 0.0 {
+        return false;
+    }
+    let t = (b1 - a1).perp_dot(db) / denom;
+    let u = (b1 - a1).perp_dot(da) / denom;
+    (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)
+}
+
+pub fn circle_polygon(center: Vec2, radius: f32, polygon: &[Vec2]) -> bool {
+    point_in_polygon(center, polygon)
+        || edges(polygon).any(|(a, b)| point_segment_distance(center, a, b) < radius)
+}
+
+pub fn polygon_polygon(polygon1: &[Vec2], polygon2: &[Vec2]) -> bool {
+    // either an edge crosses, or one outline sits entirely inside the other
+    edges(polygon1).any(|(a1, a2)| {
+        edges(polygon2).any(|(b1, b2)| segments_intersect(a1, a2, b1, b2))
+    })
+        || point_in_polygon(polygon1[0], polygon2)
+        || point_in_polygon(polygon2[0], polygon1)
+}
+
+// bodies without a polygon are treated as their circle. body1 sits at the origin, body2 at dir
+fn narrowphase(
+    shapes: &Query<(&Rotation, &PolygonCollider)>,
+    entity1: Entity,
+    r1: f32,
+    entity2: Entity,
+    r2: f32,
+    dir: Vec2,
+) -> bool {
+    match (shapes.get(entity1), shapes.get(entity2)) {
+        (Ok((rot1, poly1)), Ok((rot2, poly2))) => {
+            polygon_polygon(&poly1.placed(Vec2::ZERO, rot1.0), &poly2.placed(dir, rot2.0))
+        }
+        (Ok((rot1, poly1)), Err(_)) => circle_polygon(dir, r2, &poly1.placed(Vec2::ZERO, rot1.0)),
+        (Err(_), Ok((rot2, poly2))) => circle_polygon(Vec2::ZERO, r1, &poly2.placed(dir, rot2.0)),
+        (Err(_), Err(_)) => true,
+    }
+}
+
 #[derive(Event)]
 pub struct Collision {
     pub entity1: Entity,
@@ -91,32 +295,160 @@ pub struct Collision {
     pub collide_dist: f32,
 }
 
+// collision layers, a body is a member of some and filters which others it interacts with
+pub const LAYER_ASTEROID: u32 = 1 << 0;
+pub const LAYER_SHIP: u32 = 1 << 1;
+pub const LAYER_BULLET: u32 = 1 << 2;
+pub const LAYER_MISSILE: u32 = 1 << 3;
+
 // lets call asteroids team 0
-#[derive(Component, Debug)]
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Collider {
     pub team: u32,
+    pub membership: u32,
+    pub filter: u32,
 }
 
+impl Collider {
+    // both sides have to accept each other
+    pub fn interacts(&self, other: &Collider) -> bool {
+        self.membership & other.filter != 0 && other.membership & self.filter != 0
+    }
+}
+
+// hit points, the owner decides what happens once they run out
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn full(max: f32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+// dealt to whatever the body hits on the other team
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Damage(pub f32);
+
+// attacker is whoever gets the credit, the shooter rather than its bullet
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Damaged {
+    pub attacker: Entity,
+    pub victim: Entity,
+    pub amount: f32,
+    // health ran out with this hit
+    pub fatal: bool,
+}
+
+// collision detection skips the body until then, e.g. a freshly respawned ship
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Invulnerable {
+    pub until: Duration,
+}
+
+fn layers_interact(colliders: &Query<&Collider>, entity1: Entity, entity2: Entity) -> bool {
+    match (colliders.get(entity1), colliders.get(entity2)) {
+        (Ok(collider1), Ok(collider2)) => collider1.interacts(collider2),
+        _ => true,
+    }
+}
+
+// distance covered over the last tick, only tracked for fast bodies
+fn motion(
+    position: &Position,
+    previous: Option<&PreviousPosition>,
+    fast: bool,
+    grid: &Grid,
+) -> Vec2 {
+    match previous {
+        Some(previous) if fast => grid.wrapped_delta(previous.0, position.0),
+        _ => Vec2::ZERO,
+    }
+}
+
+type Body = (
+    Entity,
+    &'static Position,
+    Option<&'static PreviousPosition>,
+    &'static RigidBody,
+    Has<FastMoving>,
+);
+
 fn collisions(
-    mut bodies: Query<(Entity, &Position, &RigidBody)>,
+    bodies: Query<Body>,
+    shapes: Query<(&Rotation, &PolygonCollider)>,
+    colliders: Query<&Collider>,
+    invulnerable: Query<(), With<Invulnerable>>,
+    mut broadphase: ResMut<SpatialHash>,
     mut collision_writer: EventWriter<Collision>,
+    grid: Res<Grid>,
 ) {
-    let mut combinations = bodies.iter_combinations_mut();
-    while let Some([(entity1, pos1, body1), (entity2, pos2, body2)]) = combinations.fetch_next() {
-        let (dir, dist, collide_dist) = collide(pos1.0, pos2.0, body1.radius, body2.radius);
-        if dist < collide_dist {
-            //console::log_1(&"Sending CollisionEvent".into());
-            collision_writer.send(Collision {
-                entity1,
-                entity2,
-                dir,
-                dist,
-                collide_dist,
-            });
+    // fast bodies are bounded by their whole path over the last tick
+    broadphase.rebuild(
+        bodies.iter().map(|(entity, position, previous, body, fast)| {
+            let motion = motion(position, previous, fast, &grid);
+            (
+                entity,
+                position.0 - motion * 0.5,
+                body.radius + motion.length() * 0.5,
+            )
+        }),
+        &grid,
+    );
+    broadphase.for_each_pair(|(entity1, _, _), (entity2, _, _)| {
+        if !layers_interact(&colliders, entity1, entity2)
+            || invulnerable.contains(entity1)
+            || invulnerable.contains(entity2)
+        {
+            return;
         }
-    }
+        if let (Ok((_, pos1, prev1, body1, fast1)), Ok((_, pos2, prev2, body2, fast2))) =
+            (bodies.get(entity1), bodies.get(entity2))
+        {
+            let (mut dir, mut dist, collide_dist) =
+                collide(pos1.0, pos2.0, body1.radius, body2.radius, &grid);
+            let motion = motion(pos1, prev1, fast1, &grid) - motion(pos2, prev2, fast2, &grid);
+            if dist >= collide_dist && motion != Vec2::ZERO {
+                // sweep body1 relative to body2 from last tick to now, report the closest approach
+                let closest = closest_point_on_segment(Vec2::ZERO, -dir - motion, -dir);
+                dir = -closest;
+                dist = closest.length();
+            }
+            if dist < collide_dist
+                && narrowphase(&shapes, entity1, body1.radius, entity2, body2.radius, dir)
+            {
+                //console::log_1(&"Sending CollisionEvent".into());
+                collision_writer.send(Collision {
+                    entity1,
+                    entity2,
+                    dir,
+                    dist,
+                    collide_dist,
+                });
+            }
+        }
+    });
 }
 
+fn store_previous_position(mut obj: Query<(&mut PreviousPosition, &Position)>) {
+    for (mut previous, position) in obj.iter_mut() {
+        previous.0 = position.0;
+    }
+}
+fn store_previous_rotation(mut obj: Query<(&mut PreviousRotation, &Rotation)>) {
+    for (mut previous, rotation) in obj.iter_mut() {
+        previous.0 = rotation.0;
+    }
+}
+fn apply_forces(time: Res<Time>, mut obj: Query<(&mut Velocity, &mut Force, &RigidBody)>) {
+    for (mut velocity, mut force, body) in obj.iter_mut() {
+        velocity.0 += force.0 / body.mass * time.delta_secs();
+        force.0 = Vec2::ZERO;
+    }
+}
 fn update_position(time: Res<Time>, mut obj: Query<(&mut Position, &Velocity)>) {
     for (mut position, velocity) in &mut obj {
         position.0 += velocity.0 * time.delta_secs();
@@ -157,18 +489,284 @@ pub struct BodiesPlugin;
 impl Plugin for BodiesPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<Collision>();
+        app.add_event::<Damaged>();
+        app.init_resource::<SpatialHash>();
+        // thrust is applied along the rotation, so turn first
         app.add_systems(
-            Update,
-            (update_velocity, update_position, damping)
+            FixedUpdate,
+            (
+                (
+                    store_previous_rotation,
+                    update_angular_velocity,
+                    update_rotation,
+                    damping_angular,
+                )
+                    .chain(),
+                (
+                    store_previous_position,
+                    apply_forces,
+                    update_velocity,
+                    update_position,
+                    damping,
+                )
+                    .chain(),
+            )
                 .chain()
                 .in_set(InGameSet::UpdateEntities),
         );
         app.add_systems(
-            Update,
-            (update_angular_velocity, update_rotation, damping_angular)
-                .chain()
-                .in_set(InGameSet::UpdateEntities),
+            FixedUpdate,
+            (collisions).after(wrap_obj).in_set(InGameSet::CollisionDetection),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the original 1d exchange along the normal
+    fn elastic_exchange(
+        vel1: Vec2,
+        vel2: Vec2,
+        normal: Vec2,
+        mass1: f32,
+        mass2: f32,
+    ) -> (Vec2, Vec2) {
+        let tangent = Vec2::new(-normal.y, normal.x);
+        let vel1_normal = vel1.dot(normal);
+        let vel2_normal = vel2.dot(normal);
+        let vel1_normal_new =
+            (vel1_normal * (mass1 - mass2) + 2.0 * mass2 * vel2_normal) / (mass1 + mass2);
+        let vel2_normal_new =
+            (vel2_normal * (mass2 - mass1) + 2.0 * mass1 * vel1_normal) / (mass1 + mass2);
+        (
+            tangent * vel1.dot(tangent) + normal * vel1_normal_new,
+            tangent * vel2.dot(tangent) + normal * vel2_normal_new,
+        )
+    }
+
+    fn point(velocity: Vec2, mass: f32) -> ImpactBody {
+        ImpactBody {
+            velocity,
+            angular_velocity: 0.0,
+            mass,
+            inertia: 1.0,
+            offset: Vec2::ZERO,
+            material: PhysicsMaterial::default(),
+        }
+    }
+
+    fn disc(velocity: Vec2, offset: Vec2, material: PhysicsMaterial) -> ImpactBody {
+        let body = RigidBody {
+            radius: 0.5,
+            mass: 2.0,
+        };
+        ImpactBody {
+            velocity,
+            angular_velocity: 0.0,
+            mass: body.mass,
+            inertia: body.inertia(),
+            offset,
+            material,
+        }
+    }
+
+    #[test]
+    fn bounce_matches_elastic_exchange() {
+        let cases = [
+            (Vec2::new(1.0, 0.0), Vec2::new(-1.0, 0.0), Vec2::X, 2.0, 2.0),
+            (Vec2::new(2.0, 1.0), Vec2::new(0.0, -0.5), Vec2::new(1.0, 1.0).normalize(), 1.0, 3.0),
+            (Vec2::new(0.3, -2.0), Vec2::new(0.1, 0.4), Vec2::new(0.2, -1.0).normalize(), 5.0, 0.5),
+        ];
+        for (vel1, vel2, normal, mass1, mass2) in cases {
+            let (expected1, expected2) = elastic_exchange(vel1, vel2, normal, mass1, mass2);
+            let ((vel1_new, _), (vel2_new, _)) =
+                collision_bounce(&point(vel1, mass1), &point(vel2, mass2), normal);
+            assert!(vel1_new.abs_diff_eq(expected1, 1e-5));
+            assert!(vel2_new.abs_diff_eq(expected2, 1e-5));
+        }
+    }
+
+    #[test]
+    fn separating_bodies_are_untouched() {
+        let ((vel1, _), (vel2, _)) = collision_bounce(
+            &point(Vec2::new(-1.0, 0.0), 2.0),
+            &point(Vec2::new(1.0, 0.0), 2.0),
+            Vec2::X,
+        );
+        assert_eq!(vel1, Vec2::new(-1.0, 0.0));
+        assert_eq!(vel2, Vec2::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn restitution_scales_rebound() {
+        let material = PhysicsMaterial {
+            restitution: 0.5,
+            friction: 0.0,
+        };
+        let ((vel1, _), (vel2, _)) = collision_bounce(
+            &disc(Vec2::new(1.0, 0.0), Vec2::X * 0.5, material),
+            &disc(Vec2::new(-1.0, 0.0), Vec2::X * -0.5, material),
+            Vec2::X,
+        );
+        assert!(vel1.abs_diff_eq(Vec2::new(-0.5, 0.0), 1e-5));
+        assert!(vel2.abs_diff_eq(Vec2::new(0.5, 0.0), 1e-5));
+
+        // against a perfectly elastic body the rebound is still the lower one
+        let elastic = PhysicsMaterial {
+            restitution: 1.0,
+            friction: 0.0,
+        };
+        let ((vel1, _), (vel2, _)) = collision_bounce(
+            &disc(Vec2::new(1.0, 0.0), Vec2::X * 0.5, material),
+            &disc(Vec2::new(-1.0, 0.0), Vec2::X * -0.5, elastic),
+            Vec2::X,
+        );
+        assert!(vel1.abs_diff_eq(Vec2::new(-0.5, 0.0), 1e-5));
+        assert!(vel2.abs_diff_eq(Vec2::new(0.5, 0.0), 1e-5));
+
+        // and a dead one doesn't bounce at all
+        let dead = PhysicsMaterial {
+            restitution: 0.0,
+            friction: 0.0,
+        };
+        let ((vel1, _), (vel2, _)) = collision_bounce(
+            &disc(Vec2::new(1.0, 0.0), Vec2::X * 0.5, dead),
+            &disc(Vec2::new(-1.0, 0.0), Vec2::X * -0.5, elastic),
+            Vec2::X,
+        );
+        assert!(vel1.abs_diff_eq(Vec2::ZERO, 1e-5));
+        assert!(vel2.abs_diff_eq(Vec2::ZERO, 1e-5));
+    }
+
+    #[test]
+    fn glancing_hit_with_friction_spins() {
+        let material = PhysicsMaterial {
+            restitution: 1.0,
+            friction: 0.5,
+        };
+        // body1 moves right and up into body2, sitting to its right
+        let ((vel1, spin1), (vel2, spin2)) = collision_bounce(
+            &disc(Vec2::new(1.0, 1.0), Vec2::X * 0.5, material),
+            &disc(Vec2::ZERO, Vec2::X * -0.5, material),
+            Vec2::X,
         );
-        app.add_systems(Update, (collisions).in_set(InGameSet::CollisionDetection));
+        assert!(spin1 != 0.0 && spin2 != 0.0);
+        // friction drags body2 along the direction body1 slid
+        assert!(vel2.y > 0.0);
+        assert!(vel1.y < 1.0);
+        // equal and opposite linear impulse
+        assert!((vel1 + vel2).abs_diff_eq(Vec2::new(1.0, 1.0), 1e-5));
+    }
+
+    fn square(center: Vec2, half: f32) -> Vec<Vec2> {
+        vec![
+            center + Vec2::new(-half, -half),
+            center + Vec2::new(half, -half),
+            center + Vec2::new(half, half),
+            center + Vec2::new(-half, half),
+        ]
+    }
+
+    // a U opening upwards, the notch between the arms is outside
+    fn notched() -> Vec<Vec2> {
+        vec![
+            Vec2::new(-2.0, -1.0),
+            Vec2::new(2.0, -1.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(1.0, 2.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(-1.0, 2.0),
+            Vec2::new(-2.0, 2.0),
+        ]
+    }
+
+    #[test]
+    fn concave_notch_is_outside() {
+        let shape = notched();
+        assert!(point_in_polygon(Vec2::new(1.5, 1.5), &shape));
+        assert!(point_in_polygon(Vec2::new(0.0, -0.5), &shape));
+        assert!(!point_in_polygon(Vec2::new(0.0, 1.0), &shape));
+        // inside the bounding box, but sitting in the notch clear of the arms
+        assert!(!circle_polygon(Vec2::new(0.0, 1.2), 0.5, &shape));
+        assert!(circle_polygon(Vec2::new(0.0, 1.2), 1.5, &shape));
+        assert!(!polygon_polygon(&square(Vec2::new(0.0, 1.0), 0.5), &shape));
+        assert!(polygon_polygon(&square(Vec2::new(0.0, 1.0), 1.2), &shape));
+    }
+
+    #[test]
+    fn one_shape_inside_another() {
+        let outer = square(Vec2::ZERO, 2.0);
+        let inner = square(Vec2::new(0.3, -0.2), 0.5);
+        // no edges cross, either way round
+        assert!(polygon_polygon(&outer, &inner));
+        assert!(polygon_polygon(&inner, &outer));
+        assert!(circle_polygon(Vec2::new(0.5, 0.5), 0.1, &outer));
+        assert!(circle_polygon(Vec2::ZERO, 3.0, &inner));
+    }
+
+    #[test]
+    fn touching_shapes() {
+        let left = square(Vec2::ZERO, 1.0);
+        // outlines meeting along an edge or at a corner count, the segments are closed
+        assert!(polygon_polygon(&left, &square(Vec2::new(2.0, 0.0), 1.0)));
+        assert!(polygon_polygon(&left, &square(Vec2::new(2.0, 2.0), 1.0)));
+        assert!(!polygon_polygon(&left, &square(Vec2::new(2.5, 0.0), 1.0)));
+        // like two circles, a circle resting exactly on an edge isn't a hit yet
+        assert!(!circle_polygon(Vec2::new(2.0, 0.0), 1.0, &left));
+        assert!(circle_polygon(Vec2::new(1.999, 0.0), 1.0, &left));
+    }
+
+    // a bullet that covers a whole metre in one tick, straight across a thin wall at the origin
+    fn hits_thin_wall(fast: bool) -> usize {
+        let mut app = App::new();
+        app.add_event::<Collision>();
+        app.init_resource::<SpatialHash>();
+        app.insert_resource(Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        });
+        app.add_systems(Update, collisions);
+        app.world_mut().spawn((
+            Position(Vec2::ZERO),
+            Rotation(0.0),
+            RigidBody {
+                radius: 0.2,
+                mass: 1.0,
+            },
+            PolygonCollider {
+                vertices: square(Vec2::ZERO, 0.2)
+                    .into_iter()
+                    .map(|vertex| vertex * Vec2::new(0.05, 1.0))
+                    .collect(),
+            },
+        ));
+        let mut bullet = app.world_mut().spawn((
+            Position(Vec2::new(0.5, 0.05)),
+            PreviousPosition(Vec2::new(-0.5, 0.05)),
+            RigidBody {
+                radius: 0.02,
+                mass: 0.01,
+            },
+        ));
+        if fast {
+            bullet.insert(FastMoving);
+        }
+        app.update();
+        app.world()
+            .resource::<Events<Collision>>()
+            .iter_current_update_events()
+            .count()
+    }
+
+    #[test]
+    fn fast_bodies_dont_tunnel() {
+        // only checking where it ends up, it skips right over the wall
+        assert_eq!(hits_thin_wall(false), 0);
+        assert_eq!(hits_thin_wall(true), 1);
     }
 }
@@ -0,0 +1,168 @@
+use crate::grid::Grid;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+// uniform grid over the wrapped world, rebuilt every frame. cells are at least as wide as the
+// largest body, so any two overlapping bodies sit in the same or neighbouring cells (wrapping around)
+#[derive(Resource, Default)]
+pub struct SpatialHash {
+    origin: Vec2,
+    cell_size: Vec2,
+    cell_count: IVec2,
+    bodies: Vec<(Entity, Vec2, f32)>,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+// small worlds can have fewer than three cells across, don't visit the same cell twice
+fn neighbours(cell: i32, count: i32) -> impl Iterator<Item = i32> {
+    let offsets = if count >= 3 { -1..=1 } else { 0..=count - 1 };
+    offsets.map(move |offset| (cell + offset).rem_euclid(count))
+}
+
+impl SpatialHash {
+    fn cell_of(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2()
+            .rem_euclid(self.cell_count)
+    }
+
+    pub fn rebuild(&mut self, bodies: impl Iterator<Item = (Entity, Vec2, f32)>, grid: &Grid) {
+        self.bodies.clear();
+        self.bodies.extend(bodies);
+
+        // fit a whole number of cells across the world so the seam lines up with a cell edge
+        let max_radius = self.bodies.iter().fold(0.0_f32, |max, body| max.max(body.2));
+        let size = grid.wrap_size();
+        self.origin = grid.wrap_min();
+        self.cell_count = (size / (max_radius * 2.0).max(f32::EPSILON))
+            .floor()
+            .max(Vec2::ONE)
+            .as_ivec2();
+        self.cell_size = size / self.cell_count.as_vec2();
+
+        // keep the cell allocations around between frames, only drop the ones left empty
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+        for index in 0..self.bodies.len() {
+            let cell = self.cell_of(self.bodies[index].1);
+            self.cells.entry(cell).or_default().push(index);
+        }
+        self.cells.retain(|_, cell| !cell.is_empty());
+    }
+
+    // visits every candidate pair once. pairs come out by the first body's index, then by
+    // neighbouring cell, which is stable between runs but not the order iter_combinations gives
+    pub fn for_each_pair(&self, mut f: impl FnMut((Entity, Vec2, f32), (Entity, Vec2, f32))) {
+        for (index, body) in self.bodies.iter().enumerate() {
+            let cell = self.cell_of(body.1);
+            for x in neighbours(cell.x, self.cell_count.x) {
+                for y in neighbours(cell.y, self.cell_count.y) {
+                    if let Some(others) = self.cells.get(&IVec2::new(x, y)) {
+                        for &other in others {
+                            if other > index {
+                                f(*body, self.bodies[other]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::bodies::collide;
+    use bevy_turborand::prelude::*;
+    use test::Bencher;
+
+    fn test_grid() -> Grid {
+        Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        }
+    }
+
+    // spread over the whole wrapped world, so some pairs straddle the seam
+    fn random_bodies(count: u32, seed: u64) -> Vec<(Entity, Vec2, f32)> {
+        let mut rng = RngComponent::with_seed(seed);
+        (0..count)
+            .map(|i| {
+                let position = Vec2::new(rng.f32_normalized() * 5.5, rng.f32_normalized() * 3.5);
+                // mix of bullet, ship and asteroid sized bodies
+                let radius = match i % 3 {
+                    0 => 0.02,
+                    1 => 0.1,
+                    _ => rng.f32() * 0.2 + 0.3,
+                };
+                (Entity::from_raw(i), position, radius)
+            })
+            .collect()
+    }
+
+    fn brute_force_pairs(bodies: &[(Entity, Vec2, f32)], grid: &Grid) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        for (i, (entity1, pos1, r1)) in bodies.iter().enumerate() {
+            for (entity2, pos2, r2) in bodies.iter().skip(i + 1) {
+                let (_, dist, collide_dist) = collide(*pos1, *pos2, *r1, *r2, grid);
+                if dist < collide_dist {
+                    pairs.push((*entity1, *entity2));
+                }
+            }
+        }
+        pairs
+    }
+
+    fn spatial_hash_pairs(
+        spatial_hash: &mut SpatialHash,
+        bodies: &[(Entity, Vec2, f32)],
+        grid: &Grid,
+    ) -> Vec<(Entity, Entity)> {
+        spatial_hash.rebuild(bodies.iter().copied(), grid);
+        let mut pairs = Vec::new();
+        spatial_hash.for_each_pair(|(entity1, pos1, r1), (entity2, pos2, r2)| {
+            let (_, dist, collide_dist) = collide(pos1, pos2, r1, r2, grid);
+            if dist < collide_dist {
+                pairs.push((entity1, entity2));
+            }
+        });
+        pairs
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let grid = test_grid();
+        let mut spatial_hash = SpatialHash::default();
+        for seed in 0..20 {
+            let bodies = random_bodies(300, seed);
+            let mut expected = brute_force_pairs(&bodies, &grid);
+            let mut found = spatial_hash_pairs(&mut spatial_hash, &bodies, &grid);
+            expected.sort();
+            found.sort();
+            assert!(!expected.is_empty());
+            assert_eq!(expected, found);
+        }
+    }
+
+    #[bench]
+    fn bench_brute_force(b: &mut Bencher) {
+        let grid = test_grid();
+        let bodies = random_bodies(500, 1024);
+        b.iter(|| brute_force_pairs(&bodies, &grid));
+    }
+
+    #[bench]
+    fn bench_spatial_hash(b: &mut Bencher) {
+        let grid = test_grid();
+        let bodies = random_bodies(500, 1024);
+        let mut spatial_hash = SpatialHash::default();
+        b.iter(|| spatial_hash_pairs(&mut spatial_hash, &bodies, &grid));
+    }
+}
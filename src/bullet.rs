@@ -1,15 +1,24 @@
-use crate::{bodies::*, schedule::InGameSet, GameState};
+use crate::{
+    asteroid::{destroy_asteroids, restore_asteroids},
+    bodies::*,
+    gravity::Swallowed,
+    schedule::InGameSet,
+    snapshot::{PendingSnapshot, RestoredEntities},
+    weapon::{weapons_loaded, WeaponConfig, Weapons},
+    GameState,
+};
 use bevy::prelude::*;
 use bevy_common_assets::ron::RonAssetPlugin;
 use serde::Deserialize;
 use std::time::Duration;
 
+// meshes are sized in pixels, a 4px bullet collides as 0.02
+pub(crate) const RADIUS_PER_SIZE: f32 = 0.005;
+
 #[derive(Deserialize, Asset, Clone, Copy, TypePath)]
 struct BulletConfig {
-    speed: f32,
-    lifetime: u64,
-    size: f32,
-    color: (f32, f32, f32),
+    // hidden bullets spawned up front and recycled, shots past it spawn new entities
+    pool_size: usize,
 }
 
 #[derive(Resource)]
@@ -18,83 +27,189 @@ struct BulletConfigHandle {
 }
 
 #[derive(Resource)]
-struct BulletAssets {
+pub(crate) struct BulletAssets {
+    // one per weapon, missing when running headless
+    render: Option<Vec<BulletRender>>,
+}
+
+struct BulletRender {
     mesh: Handle<Mesh>,
     material: Handle<ColorMaterial>,
 }
 
-#[derive(Component)]
-struct Bullet;
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Bullet {
+    // index into the weapon list, for lifetime and looks
+    pub weapon: usize,
+    // hits left before the bullet is used up
+    pub pierce: u32,
+}
+
+// what a bullet already went through. a piercing bullet overlaps the same asteroid
+// for several ticks, only the first of them counts
+#[derive(Component, Clone, Debug, Default)]
+pub struct HitList(pub Vec<Entity>);
+
+// how well the pool keeps up with the fire rate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BulletPoolStats {
+    pub capacity: usize,
+    pub free: usize,
+    // shots served by a pooled entity
+    pub reused: u64,
+    // shots that found the pool empty and spawned a new one
+    pub allocated: u64,
+    // released bullets despawned because the pool was already full
+    pub dropped: u64,
+}
+
+// spent bullets are hidden and stripped of their simulation components instead of despawned,
+// so rapid fire doesn't keep allocating entities
+#[derive(Resource, Default)]
+pub struct BulletPool {
+    free: Vec<Entity>,
+    capacity: usize,
+    reused: u64,
+    allocated: u64,
+    dropped: u64,
+}
+
+impl BulletPool {
+    pub fn stats(&self) -> BulletPoolStats {
+        BulletPoolStats {
+            capacity: self.capacity,
+            free: self.free.len(),
+            reused: self.reused,
+            allocated: self.allocated,
+            dropped: self.dropped,
+        }
+    }
+}
+
+// whoever fired it, so kills can be credited
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Owner {
+    pub shooter: Entity,
+}
 
 #[derive(Bundle)]
 struct BulletBundle {
     bullet: Bullet,
+    hit_list: HitList,
+    damage: Damage,
     position: Position,
+    previous_position: PreviousPosition,
     rotation: Rotation,
+    previous_rotation: PreviousRotation,
     velocity: Velocity,
     angular_velocity: AngularVelocity,
     scale: Scale,
     spawn_time: TimeStamp,
     rigid_body: RigidBody,
+    fast_moving: FastMoving,
     collider: Collider,
 }
 
 impl BulletBundle {
-    fn new(position: Vec2, rotation: f32, spawn_time: Duration, speed: f32) -> Self {
+    // inherited is the shooter's velocity, the muzzle speed adds on top
+    fn new(
+        position: Vec2,
+        rotation: f32,
+        inherited: Vec2,
+        team: u32,
+        spawn_time: Duration,
+        weapon: usize,
+        config: &WeaponConfig,
+    ) -> Self {
         Self {
-            bullet: Bullet,
+            bullet: Bullet {
+                weapon,
+                pierce: config.pierce,
+            },
+            hit_list: HitList::default(),
+            damage: Damage(config.damage),
             position: Position(position),
+            previous_position: PreviousPosition(position),
             rotation: Rotation(rotation),
+            previous_rotation: PreviousRotation(rotation),
             angular_velocity: AngularVelocity(0.0),
             scale: Scale(1.0),
-            velocity: Velocity(Rot2::radians(rotation) * Vec2::new(0.0, speed)),
+            velocity: Velocity(Rot2::radians(rotation) * Vec2::new(0.0, config.speed) + inherited),
             spawn_time: TimeStamp(spawn_time),
-            rigid_body: RigidBody {
-                radius: 0.02,
-                mass: 2.0,
+            rigid_body: RigidBody::from_density(config.size * RADIUS_PER_SIZE, config.density),
+            fast_moving: FastMoving,
+            collider: Collider {
+                team,
+                membership: LAYER_BULLET,
+                filter: LAYER_ASTEROID,
             },
-            // TODO! inherit team from ship
-            collider: Collider { team: 1 },
         }
     }
 }
 
-fn load_config(
-    asset_server: Res<AssetServer>,
-    mut commands: Commands,
-) {
+fn load_config(asset_server: Res<AssetServer>, mut commands: Commands) {
     let config = asset_server.load("a.bullet.ron");
-    commands.insert_resource(BulletConfigHandle {
-        config,
-    });
+    commands.insert_resource(BulletConfigHandle { config });
 }
 
 fn load_bullet(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    configs: Res<Assets<BulletConfig>>,
-    config_handle: Res<BulletConfigHandle>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
+    weapons: Res<Weapons>,
+    configs: Res<Assets<WeaponConfig>>,
+    bullet_configs: Res<Assets<BulletConfig>>,
+    bullet_config_handle: Res<BulletConfigHandle>,
     bullet_assets: Option<Res<BulletAssets>>,
 ) {
     if bullet_assets.is_some() {
         return;
     }
-    if let Some(config) = configs.get(config_handle.config.id()) {
-        let shape = Circle::new(config.size);
-        let color = Color::srgb(config.color.0, config.color.1, config.color.2);
+    let Some(bullet_config) = bullet_configs.get(bullet_config_handle.config.id()) else {
+        return;
+    };
+    let render = match (meshes, materials) {
+        (Some(mut meshes), Some(mut materials)) => Some(
+            (0..weapons.len())
+                .filter_map(|index| weapons.get(&configs, index))
+                .map(|config| {
+                    let shape = Circle::new(config.size);
+                    let color = Color::srgb(config.color.0, config.color.1, config.color.2);
 
-        let mesh = meshes.add(shape);
-        let material = materials.add(color);
+                    let mesh = meshes.add(shape);
+                    let material = materials.add(color);
+                    BulletRender { mesh, material }
+                })
+                .collect(),
+        ),
+        _ => None,
+    };
 
-        commands.insert_resource(BulletAssets { mesh, material});
-    }
+    // hidden until a shot picks them up
+    let free = (0..bullet_config.pool_size)
+        .map(|_| commands.spawn(Visibility::Hidden).id())
+        .collect();
+    commands.insert_resource(BulletPool {
+        free,
+        capacity: bullet_config.pool_size,
+        ..default()
+    });
+    commands.insert_resource(BulletAssets { render });
+}
+
+pub fn bullets_loaded(bullet_assets: Option<Res<BulletAssets>>) -> bool {
+    bullet_assets.is_some()
 }
 
+// position is the muzzle, not the centre of the shooter
 #[derive(Event, Debug)]
 pub struct CreateBullet {
+    pub shooter: Entity,
+    pub team: u32,
     pub position: Vec2,
     pub rotation: f32,
+    pub velocity: Vec2,
+    pub weapon: usize,
 }
 
 // TODO! switch to spawning bullets with an event
@@ -103,85 +218,280 @@ fn spawn_bullet(
     mut commands: Commands,
     mut events: EventReader<CreateBullet>,
     bullet_assets: Option<Res<BulletAssets>>,
+    mut pool: ResMut<BulletPool>,
     time: Res<Time>,
-    configs: Res<Assets<BulletConfig>>,
-    config_handle: Res<BulletConfigHandle>,
+    weapons: Res<Weapons>,
+    configs: Res<Assets<WeaponConfig>>,
 ) {
-    if let Some(config) = configs.get(config_handle.config.id()) {
-        if let Some(assets) = bullet_assets {
-            for event in events.read() {
-                commands.spawn((
-                    BulletBundle::new(event.position, event.rotation, time.elapsed(), config.speed),
-                    Mesh2d(assets.mesh.clone()),
-                    MeshMaterial2d(assets.material.clone()),
-                    Transform::default(),
-                ));
+    if let Some(assets) = bullet_assets {
+        for event in events.read() {
+            if let Some(config) = weapons.get(&configs, event.weapon) {
+                let bundle = BulletBundle::new(
+                    event.position,
+                    event.rotation,
+                    event.velocity,
+                    event.team,
+                    time.elapsed(),
+                    event.weapon,
+                    config,
+                );
+                acquire_bullet(&mut commands, &mut pool, &assets, event.weapon, bundle).insert(
+                    Owner {
+                        shooter: event.shooter,
+                    },
+                );
             }
         }
     }
 }
 
+// falls back to spawning a new entity when the pool has run dry
+fn acquire_bullet<'a>(
+    commands: &'a mut Commands,
+    pool: &mut BulletPool,
+    assets: &BulletAssets,
+    weapon: usize,
+    bundle: BulletBundle,
+) -> EntityCommands<'a> {
+    let mut bullet = match pool.free.pop() {
+        Some(entity) => {
+            pool.reused += 1;
+            let mut bullet = commands.entity(entity);
+            bullet.insert(bundle);
+            bullet
+        }
+        None => {
+            pool.allocated += 1;
+            commands.spawn(bundle)
+        }
+    };
+    if let Some(render) = assets.render.as_ref().and_then(|render| render.get(weapon)) {
+        bullet.insert((
+            Mesh2d(render.mesh.clone()),
+            MeshMaterial2d(render.material.clone()),
+            Transform::default(),
+            Visibility::Inherited,
+        ));
+    }
+    bullet
+}
+
+// back into the pool, or gone for good once it is full.
+// releasing twice in one tick is fine, the components only go when the commands run
+fn release_bullet(commands: &mut Commands, pool: &mut BulletPool, entity: Entity) {
+    if pool.free.contains(&entity) {
+        return;
+    }
+    if pool.free.len() >= pool.capacity {
+        pool.dropped += 1;
+        commands.entity(entity).despawn();
+        return;
+    }
+    commands
+        .entity(entity)
+        .remove::<(BulletBundle, Owner)>()
+        .insert(Visibility::Hidden);
+    pool.free.push(entity);
+}
+
+fn restore_bullets(
+    mut commands: Commands,
+    pending: Res<PendingSnapshot>,
+    bullet_assets: Res<BulletAssets>,
+    mut pool: ResMut<BulletPool>,
+    bullets: Query<Entity, With<Bullet>>,
+    restored: Res<RestoredEntities>,
+    time: Res<Time<Fixed>>,
+    weapons: Res<Weapons>,
+    configs: Res<Assets<WeaponConfig>>,
+) {
+    for entity in bullets.iter() {
+        release_bullet(&mut commands, &mut pool, entity);
+    }
+    for saved in pending.0.bullets.iter() {
+        let Some(config) = weapons.get(&configs, saved.weapon) else {
+            warn!("snapshot bullet uses missing weapon {}", saved.weapon);
+            continue;
+        };
+        let spawn_time = pending.restamp(saved.spawn_time, time.elapsed());
+        // the shooter entity doesn't survive the load, so restored bullets have no owner
+        let bundle = BulletBundle::new(
+            saved.body.position.0,
+            saved.body.rotation.0,
+            Vec2::ZERO,
+            saved.body.collider.team,
+            spawn_time.0,
+            saved.weapon,
+            config,
+        );
+        let mut bullet =
+            acquire_bullet(&mut commands, &mut pool, &bullet_assets, saved.weapon, bundle);
+        saved.body.restore(&mut bullet);
+        let hits = saved
+            .hits
+            .iter()
+            .filter_map(|&index| restored.asteroids.get(index).copied().flatten())
+            .collect();
+        bullet.insert((
+            Bullet {
+                weapon: saved.weapon,
+                pierce: saved.pierce,
+            },
+            HitList(hits),
+        ));
+    }
+}
+
 fn destroy_bullets(
     mut commands: Commands,
-    bullets: Query<(Entity, &TimeStamp), With<Bullet>>,
+    mut pool: ResMut<BulletPool>,
+    bullets: Query<(Entity, &Bullet, &TimeStamp)>,
     time: Res<Time>,
-    configs: Res<Assets<BulletConfig>>,
-    config_handle: Res<BulletConfigHandle>,
+    weapons: Res<Weapons>,
+    configs: Res<Assets<WeaponConfig>>,
 ) {
-    if let Some(config) = configs.get(config_handle.config.id()) {
-        let time_elapsed = time.elapsed();
-        for (entity, spawn_time) in &bullets {
-            if time_elapsed - spawn_time.0 > Duration::from_millis(config.lifetime) {
-                commands.entity(entity).despawn();
-            }
+    let time_elapsed = time.elapsed();
+    for (entity, bullet, spawn_time) in &bullets {
+        let lifetime = weapons
+            .get(&configs, bullet.weapon)
+            .map_or(Duration::ZERO, |config| Duration::from_millis(config.lifetime));
+        if time_elapsed - spawn_time.0 > lifetime {
+            release_bullet(&mut commands, &mut pool, entity);
         }
     }
 }
 
+// collider filters already drop pairs bullets shouldn't hit.
+// piercing bullets keep going until they run out of hits
 fn collisions_bullets(
     mut commands: Commands,
-    bullets: Query<(Entity, &Collider), With<Bullet>>,
-    colliders: Query<(Entity, &Collider)>,
+    mut pool: ResMut<BulletPool>,
+    mut bullets: Query<(Entity, &mut Bullet, &mut HitList)>,
     mut collisions: EventReader<Collision>,
 ) {
     for event in collisions.read() {
-        if let Ok((ship, ship_collider)) = bullets.get(event.entity1) {
-            if let Ok((_, collider)) = colliders.get(event.entity2) {
-                if collider.team != ship_collider.team {
-                    commands.entity(ship).despawn();
+        for (entity, other) in [
+            (event.entity1, event.entity2),
+            (event.entity2, event.entity1),
+        ] {
+            if let Ok((entity, mut bullet, mut hit_list)) = bullets.get_mut(entity) {
+                if hit_list.0.contains(&other) {
+                    continue;
                 }
-            }
-        } else if let Ok((ship, ship_collider)) = bullets.get(event.entity2) {
-            if let Ok((_, collider)) = colliders.get(event.entity1) {
-                if collider.team != ship_collider.team {
-                    commands.entity(ship).despawn();
+                hit_list.0.push(other);
+                if bullet.pierce > 0 {
+                    bullet.pierce -= 1;
+                } else {
+                    release_bullet(&mut commands, &mut pool, entity);
                 }
             }
-        } else {
-            continue;
         }
     }
 }
 
-fn despawn_bullets(mut commands: Commands, bullets: Query<Entity, With<Bullet>>) {
+fn swallowed_bullets(
+    mut commands: Commands,
+    mut pool: ResMut<BulletPool>,
+    bullets: Query<Entity, With<Bullet>>,
+    mut events: EventReader<Swallowed>,
+) {
+    for event in events.read() {
+        if let Ok(bullet) = bullets.get(event.entity) {
+            release_bullet(&mut commands, &mut pool, bullet);
+        }
+    }
+}
+
+fn despawn_bullets(
+    mut commands: Commands,
+    mut pool: ResMut<BulletPool>,
+    bullets: Query<Entity, With<Bullet>>,
+) {
     for entity in bullets.iter() {
-        commands.entity(entity).despawn();
+        release_bullet(&mut commands, &mut pool, entity);
     }
+    debug!("bullet pool: {:?}", pool.stats());
 }
 
 pub struct BulletPlugin;
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<CreateBullet>();
         app.add_plugins(RonAssetPlugin::<BulletConfig>::new(&["bullet.ron"]));
+        app.add_event::<CreateBullet>();
+        app.init_resource::<BulletPool>();
         app.add_systems(Startup, load_config);
-        app.add_systems(Update, (load_bullet).in_set(InGameSet::LoadEntities));
         app.add_systems(
             Update,
-            (destroy_bullets, collisions_bullets).in_set(InGameSet::DespawnEntities),
+            (load_bullet)
+                .in_set(InGameSet::LoadEntities)
+                .run_if(weapons_loaded),
+        );
+        app.add_systems(
+            Update,
+            // restored asteroids are only needed for their entity ids, no need to wait on the flush
+            (restore_bullets)
+                .after_ignore_deferred(restore_asteroids)
+                .in_set(InGameSet::LoadEntities)
+                .run_if(resource_exists::<PendingSnapshot>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            // one after the other, so despawns reach the table in the same order every run.
+            // destroy_asteroids checks the hit lists before this tick's hits go on them
+            (destroy_bullets, collisions_bullets, swallowed_bullets)
+                .chain()
+                .after(destroy_asteroids)
+                .in_set(InGameSet::DespawnEntities),
         );
-        app.add_systems(Update, (spawn_bullet).in_set(InGameSet::CollisionReaction));
+        app.add_systems(FixedUpdate, (spawn_bullet).in_set(InGameSet::CollisionReaction));
         app.add_systems(OnEnter(GameState::GameOver), despawn_bullets);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collide(app: &mut App, entity1: Entity, entity2: Entity) {
+        app.world_mut().send_event(Collision {
+            entity1,
+            entity2,
+            dir: Vec2::X,
+            dist: 0.0,
+            collide_dist: 0.1,
+        });
+        app.update();
+    }
+
+    // going through an asteroid takes a few ticks, that is still one hit
+    #[test]
+    fn piercing_bullet_counts_each_target_once() {
+        let mut app = App::new();
+        app.add_event::<Collision>();
+        app.init_resource::<BulletPool>();
+        app.add_systems(Update, collisions_bullets);
+        let bullet = app
+            .world_mut()
+            .spawn((
+                Bullet {
+                    weapon: 0,
+                    pierce: 1,
+                },
+                HitList::default(),
+            ))
+            .id();
+        let first = app.world_mut().spawn_empty().id();
+        let second = app.world_mut().spawn_empty().id();
+
+        for _ in 0..3 {
+            collide(&mut app, bullet, first);
+        }
+        assert_eq!(app.world().get::<Bullet>(bullet).unwrap().pierce, 0);
+        assert_eq!(app.world().get::<HitList>(bullet).unwrap().0, vec![first]);
+
+        // either side of the pair
+        collide(&mut app, second, bullet);
+        assert!(app.world().get::<Bullet>(bullet).is_none());
+    }
+}
@@ -1,7 +1,9 @@
+use crate::control_2d::HeldControls;
 use bevy::prelude::*;
 
 //TODO! convert parameters to private
 #[derive(Component)]
+#[require(HeldControls)]
 pub struct PlayerController {
     pub id: u32,
 }
@@ -15,6 +17,21 @@ impl Controller for PlayerController {
     }
 }
 
+// marks a player whose inputs come from a replay file instead of the keyboard
+#[derive(Component)]
+pub struct ReplayController {
+    pub id: u32,
+}
+
+impl Controller for ReplayController {
+    fn get_id(&self) -> &u32 {
+        &self.id
+    }
+    fn get_mut_id(&mut self) -> &mut u32 {
+        &mut self.id
+    }
+}
+
 trait Controller {
     fn get_id(&self) -> &u32;
     fn get_mut_id(&mut self) -> &mut u32;
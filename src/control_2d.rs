@@ -1,3 +1,4 @@
+use crate::schedule::InGameSet;
 use bevy::prelude::*;
 
 #[derive(Event)]
@@ -12,9 +13,79 @@ pub struct AccelerateAngular {
     pub direction: f32,
 }
 
+// sent when the trigger goes down or up, charging weapons charge while it is held
 #[derive(Event)]
 pub struct Shoot {
     pub controller: Entity,
+    pub held: bool,
+}
+
+#[derive(Event)]
+pub struct Hyperspace {
+    pub controller: Entity,
+}
+
+#[derive(Event)]
+pub struct SwitchWeapon {
+    pub controller: Entity,
+}
+
+#[derive(Event)]
+pub struct LaunchMissile {
+    pub controller: Entity,
+}
+
+// sent when the key goes down or up, the shield stays up while it is held
+#[derive(Event)]
+pub struct RaiseShield {
+    pub controller: Entity,
+    pub held: bool,
+}
+
+// kept on the controller, a frame can run several ticks or none between two inputs
+#[derive(Component, Default, Clone, Copy, Debug)]
+pub struct HeldControls {
+    pub trigger: bool,
+    pub shield: bool,
+    // went down since the last tick, a tap that is already up again still counts for one
+    pub trigger_pressed: bool,
+    pub shield_pressed: bool,
+}
+
+impl HeldControls {
+    pub fn firing(&self) -> bool {
+        self.trigger || self.trigger_pressed
+    }
+
+    pub fn shielding(&self) -> bool {
+        self.shield || self.shield_pressed
+    }
+}
+
+pub fn hold_controls(
+    mut controllers: Query<&mut HeldControls>,
+    mut shoot_events: EventReader<Shoot>,
+    mut shield_events: EventReader<RaiseShield>,
+) {
+    // the last tick has seen the presses it latched
+    for mut held in controllers.iter_mut() {
+        if held.trigger_pressed || held.shield_pressed {
+            held.trigger_pressed = false;
+            held.shield_pressed = false;
+        }
+    }
+    for event in shoot_events.read() {
+        if let Ok(mut held) = controllers.get_mut(event.controller) {
+            held.trigger = event.held;
+            held.trigger_pressed |= event.held;
+        }
+    }
+    for event in shield_events.read() {
+        if let Ok(mut held) = controllers.get_mut(event.controller) {
+            held.shield = event.held;
+            held.shield_pressed |= event.held;
+        }
+    }
 }
 
 pub struct Control2dPlugin;
@@ -24,5 +95,35 @@ impl Plugin for Control2dPlugin {
         app.add_event::<Accelerate>();
         app.add_event::<AccelerateAngular>();
         app.add_event::<Shoot>();
+        app.add_event::<Hyperspace>();
+        app.add_event::<RaiseShield>();
+        app.add_event::<SwitchWeapon>();
+        app.add_event::<LaunchMissile>();
+        app.add_systems(FixedUpdate, (hold_controls).in_set(InGameSet::AccumulateForces));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // down and up again before the tick ran, the tick still sees the press once
+    #[test]
+    fn tap_between_ticks_is_latched() {
+        let mut app = App::new();
+        app.add_event::<Shoot>();
+        app.add_event::<RaiseShield>();
+        app.add_systems(Update, hold_controls);
+        let controller = app.world_mut().spawn(HeldControls::default()).id();
+        for held in [true, false] {
+            app.world_mut().send_event(Shoot { controller, held });
+        }
+        app.update();
+        let held = *app.world().get::<HeldControls>(controller).unwrap();
+        assert!(held.firing());
+        assert!(!held.trigger);
+
+        app.update();
+        assert!(!app.world().get::<HeldControls>(controller).unwrap().firing());
     }
 }
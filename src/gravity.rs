@@ -0,0 +1,218 @@
+use crate::{bodies::*, grid::Grid, schedule::InGameSet};
+use bevy::prelude::*;
+use bevy_common_assets::ron::RonAssetPlugin;
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy)]
+struct WellConfig {
+    position: (f32, f32),
+    strength: f32,
+    range: f32,
+    falloff: f32,
+    event_horizon: Option<f32>,
+}
+
+#[derive(Deserialize, Asset, Clone, TypePath)]
+pub(crate) struct GravityConfig {
+    wells: Vec<WellConfig>,
+    color: (f32, f32, f32),
+}
+
+#[derive(Resource)]
+pub(crate) struct GravityConfigHandle {
+    config: Handle<GravityConfig>,
+}
+
+// point mass pulling on every rigid body in range.
+// force is strength * mass / distance^falloff, so falloff 2 is inverse square
+#[derive(Component)]
+pub struct GravitySource {
+    pub strength: f32,
+    pub range: f32,
+    pub falloff: f32,
+    // bodies that cross it are swallowed
+    pub event_horizon: Option<f32>,
+}
+
+// each plugin despawns its own bodies, next to its other despawns so they land in a fixed order
+#[derive(Event)]
+pub struct Swallowed {
+    pub entity: Entity,
+}
+
+#[derive(Bundle)]
+struct GravityWellBundle {
+    position: Position,
+    previous_position: PreviousPosition,
+    rotation: Rotation,
+    previous_rotation: PreviousRotation,
+    scale: Scale,
+    source: GravitySource,
+}
+
+impl GravityWellBundle {
+    fn new(config: &WellConfig) -> Self {
+        let position = Vec2::new(config.position.0, config.position.1);
+        Self {
+            position: Position(position),
+            previous_position: PreviousPosition(position),
+            rotation: Rotation(0.0),
+            previous_rotation: PreviousRotation(0.0),
+            scale: Scale(config.event_horizon.unwrap_or(0.05) * 100.0),
+            source: GravitySource {
+                strength: config.strength,
+                range: config.range,
+                falloff: config.falloff,
+                event_horizon: config.event_horizon,
+            },
+        }
+    }
+}
+
+fn load_config(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let config = asset_server.load("a.gravity.ron");
+    commands.insert_resource(GravityConfigHandle { config });
+}
+
+// wells are part of the level, they stay put across restarts
+fn spawn_wells(
+    mut commands: Commands,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
+    configs: Res<Assets<GravityConfig>>,
+    config_handle: Res<GravityConfigHandle>,
+    mut spawned: Local<bool>,
+) {
+    if *spawned {
+        return;
+    }
+    if let Some(config) = configs.get(config_handle.config.id()) {
+        // missing when running headless
+        let render = match (meshes, materials) {
+            (Some(mut meshes), Some(mut materials)) => Some((
+                meshes.add(Circle::new(1.0)),
+                materials.add(Color::srgb(config.color.0, config.color.1, config.color.2)),
+            )),
+            _ => None,
+        };
+        for well in config.wells.iter() {
+            let mut entity = commands.spawn(GravityWellBundle::new(well));
+            if let Some((mesh, material)) = &render {
+                entity.insert((
+                    Mesh2d(mesh.clone()),
+                    MeshMaterial2d(material.clone()),
+                    Transform::default(),
+                ));
+            }
+        }
+        *spawned = true;
+    }
+}
+
+pub fn wells_loaded(
+    configs: Res<Assets<GravityConfig>>,
+    config_handle: Res<GravityConfigHandle>,
+) -> bool {
+    configs.contains(config_handle.config.id())
+}
+
+fn apply_gravity(
+    mut commands: Commands,
+    sources: Query<(Entity, &Position, &GravitySource)>,
+    mut bodies: Query<(Entity, &Position, &RigidBody, &mut Force)>,
+    mut swallowed: EventWriter<Swallowed>,
+    grid: Res<Grid>,
+) {
+    // wells can overlap, a body still only goes down one of them
+    let mut gone = Vec::new();
+    for (source_entity, source_position, source) in sources.iter() {
+        for (entity, position, body, mut force) in bodies.iter_mut() {
+            if entity == source_entity || gone.contains(&entity) {
+                continue;
+            }
+            let dir = grid.wrapped_delta(position.0, source_position.0);
+            let dist = dir.length();
+            if dist > source.range {
+                continue;
+            }
+            if let Some(event_horizon) = source.event_horizon {
+                if dist < event_horizon {
+                    swallowed.send(Swallowed { entity });
+                    // no longer a body for the ticks until its plugin gets rid of it
+                    commands.entity(entity).remove::<RigidBody>();
+                    gone.push(entity);
+                    continue;
+                }
+            }
+            // soften the pull right at the centre so it can't blow up
+            let dist = dist.max(0.01);
+            force.0 += dir / dist * source.strength * body.mass / dist.powf(source.falloff);
+        }
+    }
+}
+
+pub struct GravityPlugin;
+
+impl Plugin for GravityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Swallowed>();
+        app.add_plugins(RonAssetPlugin::<GravityConfig>::new(&["gravity.ron"]));
+        app.add_systems(Startup, load_config);
+        app.add_systems(Update, (spawn_wells).in_set(InGameSet::LoadEntities));
+        app.add_systems(FixedUpdate, (apply_gravity).in_set(InGameSet::AccumulateForces));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn well(app: &mut App, position: Vec2) {
+        app.world_mut().spawn((
+            Position(position),
+            GravitySource {
+                strength: 1.0,
+                range: 10.0,
+                falloff: 2.0,
+                event_horizon: Some(1.0),
+            },
+        ));
+    }
+
+    // overlapping wells and ticks before the despawn still swallow it once
+    #[test]
+    fn swallowed_once() {
+        let mut app = App::new();
+        app.add_event::<Swallowed>();
+        app.insert_resource(Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        });
+        app.add_systems(Update, apply_gravity);
+        well(&mut app, Vec2::ZERO);
+        well(&mut app, Vec2::new(0.5, 0.0));
+        let body = app
+            .world_mut()
+            .spawn((
+                Position(Vec2::new(0.2, 0.0)),
+                RigidBody {
+                    radius: 0.1,
+                    mass: 1.0,
+                },
+                Force(Vec2::ZERO),
+            ))
+            .id();
+        let mut swallowed = 0;
+        for _ in 0..3 {
+            app.update();
+            let events = app.world().resource::<Events<Swallowed>>();
+            swallowed += events
+                .iter_current_update_events()
+                .filter(|event| event.entity == body)
+                .count();
+        }
+        assert_eq!(swallowed, 1);
+    }
+}
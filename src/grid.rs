@@ -1,4 +1,4 @@
-use crate::{bodies::*, schedule::InGameSet};
+use crate::{bodies::*, schedule::InGameSet, Headless};
 use bevy::{prelude::*, window::WindowResized};
 use serde::Deserialize;
 
@@ -11,21 +11,48 @@ pub struct Grid {
     pub width_half: f32,
 }
 
+impl Grid {
+    // objects wrap slightly offscreen so they can leave the screen fully before wrapping
+    pub fn wrap_min(&self) -> Vec2 {
+        Vec2::new(-self.width_half - self.extends, -self.height_half - self.extends)
+    }
+
+    pub fn wrap_size(&self) -> Vec2 {
+        Vec2::new(
+            self.width_half * 2.0 + (2.0 * self.extends),
+            self.height_half * 2.0 + (2.0 * self.extends),
+        )
+    }
+
+    // shortest vector between two points on the wrapped world (minimum image convention)
+    pub fn wrapped_delta(&self, from: Vec2, to: Vec2) -> Vec2 {
+        let size = self.wrap_size();
+        let delta = to - from;
+        delta - size * (delta / size).round()
+    }
+}
+
 // so velocity numbers make sense
-fn grid_build(mut commands: Commands, window: Query<&Window>) {
-    if let Ok(window) = window.get_single() {
-        let window_height = window.resolution.height();
-        let window_width = window.resolution.width();
-        let window_scale = window.resolution.scale_factor();
-
-        //TODO! convert to config
-        let size = 100.0 * window_scale;
-        commands.insert_resource(Grid {
-            size,
-            extends: 0.5 * window_scale,
-            height_half: window_height * 0.5 / size,
-            width_half: window_width * 0.5 / size,
-        });
+fn new_grid(window_width: f32, window_height: f32, window_scale: f32) -> Grid {
+    //TODO! convert to config
+    let size = 100.0 * window_scale;
+    Grid {
+        size,
+        extends: 0.5 * window_scale,
+        height_half: window_height * 0.5 / size,
+        width_half: window_width * 0.5 / size,
+    }
+}
+
+fn grid_build(mut commands: Commands, window: Query<&Window>, headless: Option<Res<Headless>>) {
+    if let Some(headless) = headless {
+        commands.insert_resource(new_grid(headless.width, headless.height, 1.0));
+    } else if let Ok(window) = window.get_single() {
+        commands.insert_resource(new_grid(
+            window.resolution.width(),
+            window.resolution.height(),
+            window.resolution.scale_factor(),
+        ));
     }
 }
 
@@ -45,35 +72,51 @@ fn spawn_camera(mut commands: Commands) {
 }
 
 fn project_positions(
-    mut positionables: Query<(&mut Transform, &Position, &Rotation, &Scale)>,
+    mut positionables: Query<(
+        &mut Transform,
+        &Position,
+        &PreviousPosition,
+        &Rotation,
+        &PreviousRotation,
+        &Scale,
+    )>,
     grid: Res<Grid>,
+    fixed_time: Res<Time<Fixed>>,
 ) {
-    for (mut transform, position, rotation, scale) in &mut positionables {
-        let mut new_position = position.0;
+    // how far we are between the last fixed tick and the next one
+    let alpha = fixed_time.overstep_fraction();
+    for (mut transform, position, previous_position, rotation, previous_rotation, scale) in
+        &mut positionables
+    {
+        let mut new_position = interpolate_position(previous_position.0, position.0, alpha, &grid);
         new_position.x *= grid.size;
         new_position.y *= grid.size;
 
         //wrap objects around the screen
         transform.translation = new_position.extend(0.);
 
-        transform.rotation = Quat::from_rotation_z(rotation.0);
+        let new_rotation = previous_rotation.0 + (rotation.0 - previous_rotation.0) * alpha;
+        transform.rotation = Quat::from_rotation_z(new_rotation);
 
         transform.scale = Vec3::new(scale.0, scale.0, scale.0)
     }
 }
 
-fn wrap_obj(mut obj: Query<&mut Position>, grid: Res<Grid>) {
+fn interpolate_position(previous: Vec2, current: Vec2, alpha: f32, grid: &Grid) -> Vec2 {
+    // a jump of more than half the screen in one tick means the object wrapped, don't smear it across
+    let delta = current - previous;
+    if delta.x.abs() > grid.width_half || delta.y.abs() > grid.height_half {
+        return current;
+    }
+    previous + delta * alpha
+}
+
+pub fn wrap_obj(mut obj: Query<&mut Position>, grid: Res<Grid>) {
+    let min = grid.wrap_min();
+    let size = grid.wrap_size();
     for mut position in &mut obj {
-        position.0.x = wrap_around(
-            position.0.x,
-            -grid.width_half - grid.extends,
-            grid.width_half * 2.0 + (2.0 * grid.extends),
-        );
-        position.0.y = wrap_around(
-            position.0.y,
-            -grid.height_half - grid.extends,
-            grid.height_half * 2.0 + (2.0 * grid.extends),
-        );
+        position.0.x = wrap_around(position.0.x, min.x, size.x);
+        position.0.y = wrap_around(position.0.y, min.y, size.y);
     }
 }
 
@@ -87,9 +130,13 @@ pub struct GridPlugin;
 
 impl Plugin for GridPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (spawn_camera, grid_build));
-        app.add_systems(Update, (wrap_obj).in_set(InGameSet::UpdateEntities));
-        app.add_systems(Update, (on_resize).in_set(InGameSet::MenuInput));
-        app.add_systems(Update, (project_positions).in_set(InGameSet::RenderSetup));
+        app.add_systems(Startup, grid_build);
+        // after every body has moved, before anything looks at positions
+        app.add_systems(FixedUpdate, (wrap_obj).in_set(InGameSet::CollisionDetection));
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(Startup, spawn_camera);
+            app.add_systems(Update, (on_resize).in_set(InGameSet::MenuInput));
+            app.add_systems(Update, (project_positions).in_set(InGameSet::RenderSetup));
+        }
     }
 }
@@ -0,0 +1,107 @@
+use crate::{
+    schedule::InGameSet,
+    ship::{CurrentWeapon, Magazine, Shield, Ship},
+    weapon::{WeaponConfig, Weapons},
+    Headless,
+};
+use bevy::{prelude::*, sprite::Anchor};
+
+#[derive(Component)]
+struct AmmoDisplay;
+
+#[derive(Component)]
+struct ShieldDisplay;
+
+const SHIELD_BAR: usize = 10;
+
+fn spawn_hud(mut commands: Commands, asset_server: Res<AssetServer>, window: Query<&Window>) {
+    if let Ok(window) = window.get_single() {
+        let font = asset_server.load("fonts/FiraMono-Medium.ttf");
+        let text_font = TextFont {
+            font,
+            font_size: 24.0,
+            ..default()
+        };
+
+        // bottom left corner, growing to the right
+        let x = -window.resolution.width() / 2.0 + 20.0;
+        let y = -window.resolution.height() / 2.0 + 30.0;
+        commands.spawn((
+            AmmoDisplay,
+            Text2d::new(""),
+            text_font.clone(),
+            TextLayout::new_with_justify(JustifyText::Left),
+            Anchor::CenterLeft,
+            Transform::from_translation(Vec3::new(x, y, 0.0)),
+        ));
+        // one line above
+        commands.spawn((
+            ShieldDisplay,
+            Text2d::new(""),
+            text_font,
+            TextLayout::new_with_justify(JustifyText::Left),
+            Anchor::CenterLeft,
+            Transform::from_translation(Vec3::new(x, y + 30.0, 0.0)),
+        ));
+    }
+}
+
+fn update_ammo(
+    magazines: Query<(&Magazine, &CurrentWeapon), With<Ship>>,
+    mut displays: Query<&mut Text2d, With<AmmoDisplay>>,
+    weapons: Option<Res<Weapons>>,
+    configs: Res<Assets<WeaponConfig>>,
+) {
+    if let Ok(mut display) = displays.get_single_mut() {
+        display.0 = match magazines.get_single() {
+            Ok((magazine, weapon)) => {
+                let name = weapons
+                    .as_ref()
+                    .and_then(|weapons| weapons.get(&configs, weapon.index))
+                    .map_or("", |config| config.name.as_str());
+                if magazine.rounds == 0 {
+                    format!("{} reloading", name)
+                } else {
+                    format!("{} {}", name, "|".repeat(magazine.rounds as usize))
+                }
+            }
+            Err(_) => String::new(),
+        };
+    }
+}
+
+// fills back up while the shield recharges
+fn update_shield(
+    shields: Query<&Shield, With<Ship>>,
+    mut displays: Query<&mut Text2d, With<ShieldDisplay>>,
+) {
+    if let Ok(mut display) = displays.get_single_mut() {
+        display.0 = match shields.get_single() {
+            Ok(shield) => {
+                let filled = (shield.energy * SHIELD_BAR as f32).ceil() as usize;
+                format!(
+                    "[{}{}]{}",
+                    "#".repeat(filled),
+                    "-".repeat(SHIELD_BAR - filled),
+                    if shield.up { " up" } else { "" }
+                )
+            }
+            Err(_) => String::new(),
+        };
+    }
+}
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        // nothing to draw on without a window
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(Startup, spawn_hud);
+            app.add_systems(
+                Update,
+                (update_ammo, update_shield).in_set(InGameSet::RenderSetup),
+            );
+        }
+    }
+}
@@ -1,15 +1,37 @@
-use crate::control::PlayerController;
+use crate::control::ReplayController;
 use crate::control_2d::*;
 use bevy::prelude::*;
 
+// what to send for a key the controller holds down, oldest first.
+// edges go out as they happen, so a tap between two ticks or within one frame isn't lost.
+// otherwise the state is repeated until the controller has caught up with it
+fn hold_changes(keyboard_input: &ButtonInput<KeyCode>, key: KeyCode, held: bool) -> Vec<bool> {
+    let pressed = keyboard_input.pressed(key);
+    if keyboard_input.just_pressed(key) && keyboard_input.just_released(key) {
+        // went both ways within the frame, it ends up where it is now
+        vec![!pressed, pressed]
+    } else if keyboard_input.just_pressed(key)
+        || keyboard_input.just_released(key)
+        || pressed != held
+    {
+        vec![pressed]
+    } else {
+        Vec::new()
+    }
+}
+
 pub fn handle_player_input(
-    controllers: Query<(Entity, &PlayerController)>,
+    controllers: Query<(Entity, &HeldControls), Without<ReplayController>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut accel_writer: EventWriter<Accelerate>,
     mut accel_ang_writer: EventWriter<AccelerateAngular>,
     mut shoot_writer: EventWriter<Shoot>,
+    mut hyperspace_writer: EventWriter<Hyperspace>,
+    mut shield_writer: EventWriter<RaiseShield>,
+    mut switch_writer: EventWriter<SwitchWeapon>,
+    mut missile_writer: EventWriter<LaunchMissile>,
 ) {
-    for (entity, _controller) in controllers.iter() {
+    for (entity, held) in controllers.iter() {
         // TODO! add input handling to route keys to specific players
         let mut acceleration = Accelerate {
             controller: entity,
@@ -37,8 +59,53 @@ pub fn handle_player_input(
         }
         accel_ang_writer.send(accel_angular);
 
-        if keyboard_input.pressed(KeyCode::Space) {
-            shoot_writer.send(Shoot { controller: entity });
+        // only changes are sent, the controller remembers the rest
+        for trigger in hold_changes(&keyboard_input, KeyCode::Space, held.trigger) {
+            shoot_writer.send(Shoot {
+                controller: entity,
+                held: trigger,
+            });
+        }
+
+        if keyboard_input.pressed(KeyCode::ShiftLeft) {
+            hyperspace_writer.send(Hyperspace { controller: entity });
+        }
+
+        for shield in hold_changes(&keyboard_input, KeyCode::ControlLeft, held.shield) {
+            shield_writer.send(RaiseShield {
+                controller: entity,
+                held: shield,
+            });
+        }
+
+        // once per press, holding it would spin through the whole list
+        if keyboard_input.just_pressed(KeyCode::Tab) {
+            switch_writer.send(SwitchWeapon { controller: entity });
+        }
+
+        if keyboard_input.pressed(KeyCode::AltLeft) {
+            missile_writer.send(LaunchMissile { controller: entity });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tap_within_a_frame_goes_down_and_up() {
+        let mut keyboard_input = ButtonInput::<KeyCode>::default();
+        keyboard_input.press(KeyCode::Space);
+        keyboard_input.release(KeyCode::Space);
+        assert_eq!(hold_changes(&keyboard_input, KeyCode::Space, false), vec![true, false]);
+
+        // nothing new once the controller has caught up
+        keyboard_input.clear();
+        assert!(hold_changes(&keyboard_input, KeyCode::Space, false).is_empty());
+        keyboard_input.press(KeyCode::Space);
+        keyboard_input.clear();
+        assert_eq!(hold_changes(&keyboard_input, KeyCode::Space, false), vec![true]);
+        assert!(hold_changes(&keyboard_input, KeyCode::Space, true).is_empty());
+    }
+}
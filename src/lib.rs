@@ -1,13 +1,25 @@
+#![cfg_attr(test, feature(test))]
+
 use crate::asteroid::*;
 use crate::bodies::*;
 use crate::bullet::*;
+use crate::gravity::*;
 use crate::grid::*;
 use crate::input::*;
+use crate::missile::*;
 use crate::score::*;
 use crate::ship::*;
 use crate::spawner::*;
 use crate::states::*;
+use crate::wave::*;
+use bevy::asset::AssetPlugin;
 use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use hud::HudPlugin;
+use replay::ReplayPlugin;
+use snapshot::SnapshotPlugin;
+use weapon::WeaponPlugin;
+use std::path::PathBuf;
 
 use bevy_turborand::prelude::*;
 use control_2d::Control2dPlugin;
@@ -16,34 +28,131 @@ use schedule::SchudulePlugin;
 
 mod asteroid;
 mod bodies;
+mod broadphase;
 mod bullet;
 mod control;
 mod control_2d;
+mod gravity;
 mod grid;
+mod hud;
 mod input;
+mod missile;
+mod replay;
 mod schedule;
 mod score;
 mod ship;
+mod snapshot;
 mod spawner;
 mod states;
+mod wave;
+mod weapon;
+
+// what tests, tools and bots driving the game from outside get to see
+pub use asteroid::Asteroid;
+pub use bullet::{Bullet, BulletPool, BulletPoolStats, Owner};
+pub use bodies::{Damage, Damaged, Health, Position, Rotation, Velocity};
+pub use control::PlayerController;
+pub use control_2d::{
+    Accelerate, AccelerateAngular, Hyperspace, LaunchMissile, RaiseShield, Shoot, SwitchWeapon,
+};
+pub use missile::Missile;
+pub use replay::{InputRecording, RecordedEvent, RecordedGameOver, RecordedInput, REPLAY_VERSION};
+pub use schedule::SimulationTick;
+pub use score::Score;
+pub use ship::{CurrentWeapon, Lives, Magazine, Shield, Ship};
+pub use snapshot::{LoadSnapshot, SaveSnapshot, Snapshot, SNAPSHOT_VERSION};
+pub use states::GameState;
+pub use wave::Wave;
 
 const WORLD_SEED: u64 = 1024;
+const TICK_RATE: f64 = 60.0;
+
+// run without a window, renderer or keyboard on top of MinimalPlugins.
+// width and height are the virtual window size in pixels the grid is built from
+#[derive(Resource, Clone, Copy)]
+pub struct Headless {
+    pub width: f32,
+    pub height: f32,
+}
+
+pub struct AsteroidsGamePlugin {
+    pub tick_rate: f64,
+    pub headless: Option<Headless>,
+    // write the player inputs to this file when the game ends
+    pub record: Option<PathBuf>,
+    // play the inputs back from this file instead of the keyboard
+    pub replay: Option<PathBuf>,
+}
 
-pub struct AsteroidsGamePlugin;
+impl Default for AsteroidsGamePlugin {
+    fn default() -> Self {
+        Self {
+            tick_rate: TICK_RATE,
+            headless: None,
+            record: None,
+            replay: None,
+        }
+    }
+}
 
 impl Plugin for AsteroidsGamePlugin {
     fn build(&self, app: &mut App) {
+        // needs to be in place before the other plugins check for it
+        if let Some(headless) = self.headless {
+            // MinimalPlugins doesn't bring these along
+            if !app.is_plugin_added::<AssetPlugin>() {
+                app.add_plugins(AssetPlugin::default());
+            }
+            if !app.is_plugin_added::<StatesPlugin>() {
+                app.add_plugins(StatesPlugin);
+            }
+            app.insert_resource(headless);
+        }
+
         app.add_plugins(RngPlugin::new().with_rng_seed(WORLD_SEED));
         app.add_plugins(ScorePlugin);
-        app.add_plugins(SchudulePlugin);
+        app.add_plugins(SchudulePlugin {
+            tick_rate: self.tick_rate,
+        });
         app.add_plugins(ShipPlugin);
         app.add_plugins(BodiesPlugin);
         app.add_plugins(Control2dPlugin);
         app.add_plugins(StatePlugin);
         app.add_plugins(GridPlugin);
+        app.add_plugins(GravityPlugin);
+        app.add_plugins(WeaponPlugin);
         app.add_plugins(BulletPlugin);
+        app.add_plugins(MissilePlugin);
         app.add_plugins(AsteroidsPlugin);
+        app.add_plugins(WavePlugin);
+        app.add_plugins(HudPlugin);
+        app.add_plugins(SnapshotPlugin);
+        app.add_plugins(ReplayPlugin {
+            seed: WORLD_SEED,
+            tick_rate: self.tick_rate,
+            record: self.record.clone(),
+            replay: self.replay.clone(),
+        });
+
+        // don't start ticking until everything the simulation reads is in place
+        app.add_systems(
+            Update,
+            (finish_loading)
+                .after(InGameSet::LoadEntities)
+                .run_if(
+                    in_state(GameState::Loading)
+                        .and(resource_exists::<Grid>)
+                        .and(asteroids_loaded)
+                        .and(bullets_loaded)
+                        .and(missiles_loaded)
+                        .and(ships_loaded)
+                        .and(waves_loaded)
+                        .and(wells_loaded),
+                ),
+        );
 
-        app.add_systems(Update, (handle_player_input,).in_set(InGameSet::GameInput));
+        if self.headless.is_none() {
+            app.add_systems(Update, (handle_player_input,).in_set(InGameSet::GameInput));
+        }
     }
 }
@@ -1,8 +1,20 @@
 use asteroids::AsteroidsGamePlugin;
 use bevy::prelude::*;
+use std::path::PathBuf;
 
 // test workflow
+// --record <file> saves the inputs when the game ends, --replay <file> plays them back
 fn main() {
+    let mut game = AsteroidsGamePlugin::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => game.record = args.next().map(PathBuf::from),
+            "--replay" => game.replay = args.next().map(PathBuf::from),
+            _ => warn!("ignoring unknown argument {}", arg),
+        }
+    }
+
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
@@ -11,6 +23,6 @@ fn main() {
             }),
             ..default()
         }))
-        .add_plugins(AsteroidsGamePlugin)
+        .add_plugins(game)
         .run();
 }
@@ -0,0 +1,508 @@
+use crate::{
+    asteroid::{restore_asteroids, Asteroid},
+    bodies::*,
+    bullet::{Owner, RADIUS_PER_SIZE},
+    control::{Pawn, ShipPawn},
+    control_2d::LaunchMissile,
+    gravity::Swallowed,
+    grid::Grid,
+    schedule::InGameSet,
+    ship::{restore_ships, Ship},
+    snapshot::{PendingSnapshot, RestoredEntities},
+    GameState,
+};
+use bevy::prelude::*;
+use bevy_common_assets::ron::RonAssetPlugin;
+use serde::Deserialize;
+use std::time::Duration;
+
+// full turn until the heading is this far off, so it doesn't flutter around the target
+const SETTLE_ANGLE: f32 = 0.2;
+
+#[derive(Deserialize, Asset, Clone, TypePath)]
+pub(crate) struct MissileConfig {
+    fire_delay: u64,
+    // launch speed on top of the ship's velocity
+    speed: f32,
+    thrust: f32,
+    damping: f32,
+    // angular acceleration while steering
+    turn_rate: f32,
+    damping_angular: f32,
+    // seconds of thrust, after that the missile coasts
+    fuel: f32,
+    lifetime: u64,
+    // half angle in radians either side of the nose
+    cone: f32,
+    range: f32,
+    size: f32,
+    color: (f32, f32, f32),
+    density: f32,
+    damage: f32,
+}
+
+#[derive(Resource)]
+pub(crate) struct MissileConfigHandle {
+    config: Handle<MissileConfig>,
+}
+
+#[derive(Resource)]
+pub(crate) struct MissileAssets {
+    // missing when running headless
+    render: Option<MissileRender>,
+}
+
+struct MissileRender {
+    mesh: Handle<Mesh>,
+    material: Handle<ColorMaterial>,
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Missile {
+    pub target: Option<Entity>,
+    // seconds of thrust left
+    pub fuel: f32,
+}
+
+// kept on the ship, when it last let a missile go.
+// none until the first launch, which is always allowed
+#[derive(Component, Default)]
+pub(crate) struct MissileLauncher {
+    pub(crate) last_launch: Option<Duration>,
+}
+
+#[derive(Bundle)]
+struct MissileBundle {
+    missile: Missile,
+    damage: Damage,
+    owner: Owner,
+    position: Position,
+    previous_position: PreviousPosition,
+    rotation: Rotation,
+    previous_rotation: PreviousRotation,
+    velocity: Velocity,
+    acceleration: Acceleration,
+    damping: Damping,
+    angular_velocity: AngularVelocity,
+    angular_acceleration: AngularAcceleration,
+    angular_damping: AngularDamping,
+    scale: Scale,
+    spawn_time: TimeStamp,
+    rigid_body: RigidBody,
+    fast_moving: FastMoving,
+    collider: Collider,
+}
+
+impl MissileBundle {
+    fn new(
+        position: Vec2,
+        rotation: f32,
+        inherited: Vec2,
+        shooter: Entity,
+        team: u32,
+        target: Option<Entity>,
+        spawn_time: Duration,
+        config: &MissileConfig,
+    ) -> Self {
+        Self {
+            missile: Missile {
+                target,
+                fuel: config.fuel,
+            },
+            damage: Damage(config.damage),
+            owner: Owner { shooter },
+            position: Position(position),
+            previous_position: PreviousPosition(position),
+            rotation: Rotation(rotation),
+            previous_rotation: PreviousRotation(rotation),
+            velocity: Velocity(Rot2::radians(rotation) * Vec2::new(0.0, config.speed) + inherited),
+            acceleration: Acceleration(Vec2::ZERO),
+            damping: Damping(config.damping),
+            angular_velocity: AngularVelocity(0.0),
+            angular_acceleration: AngularAcceleration(0.0),
+            angular_damping: AngularDamping(config.damping_angular),
+            scale: Scale(1.0),
+            spawn_time: TimeStamp(spawn_time),
+            rigid_body: RigidBody::from_density(config.size * RADIUS_PER_SIZE, config.density),
+            fast_moving: FastMoving,
+            collider: Collider {
+                team,
+                membership: LAYER_MISSILE,
+                filter: LAYER_ASTEROID,
+            },
+        }
+    }
+}
+
+fn load_config(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let config = asset_server.load("a.missile.ron");
+    commands.insert_resource(MissileConfigHandle { config });
+}
+
+fn load_missiles(
+    mut commands: Commands,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
+    configs: Res<Assets<MissileConfig>>,
+    config_handle: Res<MissileConfigHandle>,
+    missile_assets: Option<Res<MissileAssets>>,
+) {
+    if missile_assets.is_some() {
+        return;
+    }
+    if let Some(config) = configs.get(config_handle.config.id()) {
+        let render = match (meshes, materials) {
+            (Some(mut meshes), Some(mut materials)) => {
+                // nose along +y like the ship
+                let shape = Triangle2d::new(
+                    Vec2::new(0.0, config.size),
+                    Vec2::new(-config.size * 0.5, -config.size),
+                    Vec2::new(config.size * 0.5, -config.size),
+                );
+                let color = Color::srgb(config.color.0, config.color.1, config.color.2);
+                Some(MissileRender {
+                    mesh: meshes.add(shape),
+                    material: materials.add(color),
+                })
+            }
+            _ => None,
+        };
+        commands.insert_resource(MissileAssets { render });
+    }
+}
+
+pub fn missiles_loaded(missile_assets: Option<Res<MissileAssets>>) -> bool {
+    missile_assets.is_some()
+}
+
+// nearest candidate inside the cone around the heading, measured across the screen wrap
+pub(crate) fn acquire_target(
+    origin: Vec2,
+    rotation: f32,
+    cone: f32,
+    range: f32,
+    candidates: impl Iterator<Item = (Entity, Vec2)>,
+    grid: &Grid,
+) -> Option<Entity> {
+    let heading = Rot2::radians(rotation) * Vec2::Y;
+    candidates
+        .filter_map(|(entity, position)| {
+            let delta = grid.wrapped_delta(origin, position);
+            let dist = delta.length();
+            (dist <= range && heading.angle_to(delta).abs() <= cone).then_some((entity, dist))
+        })
+        .fold(None, |nearest: Option<(Entity, f32)>, candidate| match nearest {
+            Some(nearest) if nearest.1 <= candidate.1 => Some(nearest),
+            _ => Some(candidate),
+        })
+        .map(|(entity, _)| entity)
+}
+
+type Launcher = (
+    Entity,
+    &'static Position,
+    &'static Rotation,
+    &'static Velocity,
+    &'static RigidBody,
+    &'static Collider,
+    &'static mut MissileLauncher,
+    &'static ShipPawn,
+);
+
+// spawned at the end of the tick like bullets
+fn launch_missiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut ships: Query<Launcher, With<Ship>>,
+    asteroids: Query<(Entity, &Position), With<Asteroid>>,
+    mut events: EventReader<LaunchMissile>,
+    missile_assets: Option<Res<MissileAssets>>,
+    grid: Res<Grid>,
+    configs: Res<Assets<MissileConfig>>,
+    config_handle: Res<MissileConfigHandle>,
+) {
+    let (Some(assets), Some(config)) = (missile_assets, configs.get(config_handle.config.id()))
+    else {
+        return;
+    };
+    let launching: Vec<Entity> = events.read().map(|event| event.controller).collect();
+    for (entity, position, rotation, velocity, rigid_body, collider, mut launcher, pawn) in
+        ships.iter_mut()
+    {
+        let time_elapsed = time.elapsed();
+        let reloading = launcher.last_launch.is_some_and(|launched| {
+            time_elapsed - launched <= Duration::from_millis(config.fire_delay)
+        });
+        if !launching.contains(pawn.get_controller()) || reloading {
+            continue;
+        }
+        launcher.last_launch = Some(time_elapsed);
+        let target = acquire_target(
+            position.0,
+            rotation.0,
+            config.cone,
+            config.range,
+            asteroids.iter().map(|(entity, position)| (entity, position.0)),
+            &grid,
+        );
+        let nose = position.0 + Rot2::radians(rotation.0) * Vec2::new(0.0, rigid_body.radius);
+        let mut missile = commands.spawn(MissileBundle::new(
+            nose,
+            rotation.0,
+            velocity.0,
+            entity,
+            collider.team,
+            target,
+            time_elapsed,
+            config,
+        ));
+        if let Some(render) = &assets.render {
+            missile.insert((
+                Mesh2d(render.mesh.clone()),
+                MeshMaterial2d(render.material.clone()),
+                Transform::default(),
+            ));
+        }
+    }
+}
+
+type Guidance = (
+    &'static mut Missile,
+    &'static Position,
+    &'static Rotation,
+    &'static mut Acceleration,
+    &'static mut AngularAcceleration,
+);
+
+// turn the nose onto the target and burn until the fuel runs out.
+// a lost target is replaced by whatever is in front of the missile now
+fn steer_missiles(
+    time: Res<Time>,
+    mut missiles: Query<Guidance>,
+    asteroids: Query<(Entity, &Position), With<Asteroid>>,
+    grid: Res<Grid>,
+    configs: Res<Assets<MissileConfig>>,
+    config_handle: Res<MissileConfigHandle>,
+) {
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
+    };
+    for (mut missile, position, rotation, mut acceleration, mut angular_acceleration) in
+        missiles.iter_mut()
+    {
+        if missile.fuel <= 0.0 {
+            acceleration.0 = Vec2::ZERO;
+            angular_acceleration.0 = 0.0;
+            continue;
+        }
+        missile.fuel -= time.delta_secs();
+        acceleration.0 = Vec2::new(0.0, config.thrust);
+
+        if !missile.target.is_some_and(|target| asteroids.contains(target)) {
+            missile.target = acquire_target(
+                position.0,
+                rotation.0,
+                config.cone,
+                config.range,
+                asteroids.iter().map(|(entity, position)| (entity, position.0)),
+                &grid,
+            );
+        }
+        angular_acceleration.0 = match missile.target.and_then(|target| asteroids.get(target).ok()) {
+            Some((_, target)) => {
+                let heading = Rot2::radians(rotation.0) * Vec2::Y;
+                let error = heading.angle_to(grid.wrapped_delta(position.0, target.0));
+                (error / SETTLE_ANGLE).clamp(-1.0, 1.0) * config.turn_rate
+            }
+            None => 0.0,
+        };
+    }
+}
+
+fn destroy_missiles(
+    mut commands: Commands,
+    missiles: Query<(Entity, &TimeStamp), With<Missile>>,
+    time: Res<Time>,
+    configs: Res<Assets<MissileConfig>>,
+    config_handle: Res<MissileConfigHandle>,
+) {
+    if let Some(config) = configs.get(config_handle.config.id()) {
+        for (entity, spawn_time) in missiles.iter() {
+            if time.elapsed() - spawn_time.0 > Duration::from_millis(config.lifetime) {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+// destroy_asteroids deals with the asteroid, the missile is spent on the first hit
+fn collisions_missiles(
+    mut commands: Commands,
+    missiles: Query<Entity, With<Missile>>,
+    mut collisions: EventReader<Collision>,
+) {
+    let mut spent = Vec::new();
+    for event in collisions.read() {
+        for entity in [event.entity1, event.entity2] {
+            if missiles.contains(entity) && !spent.contains(&entity) {
+                spent.push(entity);
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+fn swallowed_missiles(
+    mut commands: Commands,
+    missiles: Query<Entity, With<Missile>>,
+    mut events: EventReader<Swallowed>,
+) {
+    for event in events.read() {
+        if let Ok(missile) = missiles.get(event.entity) {
+            commands.entity(missile).despawn();
+        }
+    }
+}
+
+// targets and owners point at the restored asteroids and ships
+fn restore_missiles(
+    mut commands: Commands,
+    pending: Res<PendingSnapshot>,
+    restored: Res<RestoredEntities>,
+    missiles: Query<Entity, With<Missile>>,
+    missile_assets: Res<MissileAssets>,
+    time: Res<Time<Fixed>>,
+    configs: Res<Assets<MissileConfig>>,
+    config_handle: Res<MissileConfigHandle>,
+) {
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
+    };
+    for entity in missiles.iter() {
+        commands.entity(entity).despawn();
+    }
+    for saved in pending.0.missiles.iter() {
+        let target = saved
+            .target
+            .and_then(|index| restored.asteroids.get(index).copied().flatten());
+        let owner = saved.owner.and_then(|index| restored.ships.get(index).copied());
+        let spawn_time = pending.restamp(saved.spawn_time, time.elapsed());
+        let mut missile = commands.spawn(MissileBundle::new(
+            saved.body.position.0,
+            saved.body.rotation.0,
+            Vec2::ZERO,
+            owner.unwrap_or(Entity::PLACEHOLDER),
+            saved.body.collider.team,
+            target,
+            spawn_time.0,
+            config,
+        ));
+        saved.body.restore(&mut missile);
+        missile.insert(Missile {
+            target,
+            fuel: saved.fuel,
+        });
+        // the ship that fired it wasn't saved, the missile takes the credit itself
+        if owner.is_none() {
+            missile.remove::<Owner>();
+        }
+        if let Some(render) = &missile_assets.render {
+            missile.insert((
+                Mesh2d(render.mesh.clone()),
+                MeshMaterial2d(render.material.clone()),
+                Transform::default(),
+            ));
+        }
+    }
+}
+
+fn despawn_missiles(mut commands: Commands, missiles: Query<Entity, With<Missile>>) {
+    for entity in missiles.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+pub struct MissilePlugin;
+
+impl Plugin for MissilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<MissileConfig>::new(&["missile.ron"]));
+        app.add_systems(Startup, load_config);
+        app.add_systems(Update, (load_missiles).in_set(InGameSet::LoadEntities));
+        app.add_systems(
+            Update,
+            (restore_missiles)
+                .after_ignore_deferred(restore_asteroids)
+                .after_ignore_deferred(restore_ships)
+                .in_set(InGameSet::LoadEntities)
+                .run_if(resource_exists::<PendingSnapshot>),
+        );
+        app.add_systems(
+            FixedUpdate,
+            (destroy_missiles, collisions_missiles, swallowed_missiles)
+                .chain()
+                .in_set(InGameSet::DespawnEntities),
+        );
+        app.add_systems(FixedUpdate, (steer_missiles).in_set(InGameSet::AccumulateForces));
+        app.add_systems(FixedUpdate, (launch_missiles).in_set(InGameSet::CollisionReaction));
+        app.add_systems(OnEnter(GameState::GameOver), despawn_missiles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_grid() -> Grid {
+        Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        }
+    }
+
+    #[test]
+    fn picks_the_nearest_in_the_cone() {
+        let grid = test_grid();
+        let candidates = [
+            (Entity::from_raw(0), Vec2::new(0.0, 3.0)),
+            (Entity::from_raw(1), Vec2::new(0.2, 1.5)),
+            // closer, but behind the nose
+            (Entity::from_raw(2), Vec2::new(0.0, -0.5)),
+        ];
+        let target = acquire_target(Vec2::ZERO, 0.0, 0.5, 5.0, candidates.into_iter(), &grid);
+        assert_eq!(target, Some(Entity::from_raw(1)));
+    }
+
+    #[test]
+    fn looks_across_the_wrap() {
+        let grid = test_grid();
+        // the wrapped world is 7 tall, so from just under the top edge this is 1 ahead
+        let candidates = [(Entity::from_raw(0), Vec2::new(0.0, -3.0))];
+        let target = acquire_target(
+            Vec2::new(0.0, 3.0),
+            0.0,
+            0.5,
+            2.0,
+            candidates.into_iter(),
+            &grid,
+        );
+        assert_eq!(target, Some(Entity::from_raw(0)));
+    }
+
+    #[test]
+    fn ignores_out_of_range_and_cone() {
+        let grid = test_grid();
+        let candidates = [
+            (Entity::from_raw(0), Vec2::new(0.0, 4.0)),
+            (Entity::from_raw(1), Vec2::new(1.0, 0.0)),
+        ];
+        // facing +x picks up the second one
+        let rotation = -std::f32::consts::FRAC_PI_2;
+        let target = acquire_target(Vec2::ZERO, 0.0, 0.5, 2.0, candidates.into_iter(), &grid);
+        assert_eq!(target, None);
+        let target = acquire_target(Vec2::ZERO, rotation, 0.5, 2.0, candidates.into_iter(), &grid);
+        assert_eq!(target, Some(Entity::from_raw(1)));
+    }
+}
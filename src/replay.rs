@@ -0,0 +1,336 @@
+use crate::{
+    control::{PlayerController, ReplayController},
+    control_2d::{
+        Accelerate, AccelerateAngular, Hyperspace, LaunchMissile, RaiseShield, Shoot, SwitchWeapon,
+    },
+    schedule::{advance_tick, InGameSet, SimulationTick},
+    score::Score,
+    GameState,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// bump whenever old recordings can no longer be played back. that is any change to how
+// the simulation plays out too, not only to the file format
+pub const REPLAY_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RecordedInput {
+    Accelerate((f32, f32)),
+    AccelerateAngular(f32),
+    // held or let go
+    Shoot(bool),
+    Hyperspace,
+    RaiseShield(bool),
+    SwitchWeapon,
+    LaunchMissile,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RecordedEvent {
+    // fixed tick the event was consumed on
+    pub tick: u64,
+    // PlayerController id, entities aren't stable between runs
+    pub controller: u32,
+    pub input: RecordedInput,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordedGameOver {
+    pub tick: u64,
+    pub score: u16,
+}
+
+// every control event of a game, in the order the simulation saw them
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InputRecording {
+    pub version: u32,
+    pub seed: u64,
+    pub tick_rate: f64,
+    pub events: Vec<RecordedEvent>,
+    // ticks a new game was started on after the previous one ended
+    pub restarts: Vec<u64>,
+    pub game_over: Option<RecordedGameOver>,
+}
+
+// only the header, so a version mismatch is reported before the rest fails to parse
+#[derive(Deserialize)]
+struct RecordingHeader {
+    version: u32,
+}
+
+impl InputRecording {
+    pub fn new(seed: u64, tick_rate: f64) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            seed,
+            tick_rate,
+            events: Vec::new(),
+            restarts: Vec::new(),
+            game_over: None,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())?;
+        fs::write(path, text).map_err(|error| error.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let header: RecordingHeader = ron::from_str(&text).map_err(|error| error.to_string())?;
+        if header.version != REPLAY_VERSION {
+            return Err(format!(
+                "replay version {} is not supported, expected {}",
+                header.version, REPLAY_VERSION
+            ));
+        }
+        ron::from_str(&text).map_err(|error| error.to_string())
+    }
+}
+
+#[derive(Resource)]
+struct RecordPath(PathBuf);
+
+#[derive(Resource)]
+struct InputReplay {
+    recording: InputRecording,
+    next: usize,
+    next_restart: usize,
+}
+
+// route the recorded players through the replay instead of the keyboard
+fn attach_replay_controllers(
+    mut commands: Commands,
+    controllers: Query<(Entity, &PlayerController), Added<PlayerController>>,
+) {
+    for (entity, controller) in controllers.iter() {
+        commands
+            .entity(entity)
+            .insert(ReplayController { id: controller.id });
+    }
+}
+
+fn replay_inputs(
+    mut replay: ResMut<InputReplay>,
+    tick: Res<SimulationTick>,
+    controllers: Query<(Entity, &ReplayController)>,
+    mut accel_writer: EventWriter<Accelerate>,
+    mut accel_ang_writer: EventWriter<AccelerateAngular>,
+    mut shoot_writer: EventWriter<Shoot>,
+    mut hyperspace_writer: EventWriter<Hyperspace>,
+    mut shield_writer: EventWriter<RaiseShield>,
+    mut switch_writer: EventWriter<SwitchWeapon>,
+    mut missile_writer: EventWriter<LaunchMissile>,
+) {
+    while let Some(event) = replay.recording.events.get(replay.next).copied() {
+        if event.tick > tick.0 {
+            break;
+        }
+        replay.next += 1;
+        let Some((controller, _)) = controllers.iter().find(|(_, c)| c.id == event.controller)
+        else {
+            continue;
+        };
+        match event.input {
+            RecordedInput::Accelerate((x, y)) => {
+                accel_writer.send(Accelerate {
+                    controller,
+                    direction: Vec2::new(x, y),
+                });
+            }
+            RecordedInput::AccelerateAngular(direction) => {
+                accel_ang_writer.send(AccelerateAngular {
+                    controller,
+                    direction,
+                });
+            }
+            RecordedInput::Shoot(held) => {
+                shoot_writer.send(Shoot { controller, held });
+            }
+            RecordedInput::Hyperspace => {
+                hyperspace_writer.send(Hyperspace { controller });
+            }
+            RecordedInput::RaiseShield(held) => {
+                shield_writer.send(RaiseShield { controller, held });
+            }
+            RecordedInput::SwitchWeapon => {
+                switch_writer.send(SwitchWeapon { controller });
+            }
+            RecordedInput::LaunchMissile => {
+                missile_writer.send(LaunchMissile { controller });
+            }
+        }
+    }
+}
+
+// the recorded player started another game here, so does the replay
+fn replay_restarts(
+    mut replay: ResMut<InputReplay>,
+    tick: Res<SimulationTick>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if replay.recording.restarts.get(replay.next_restart) == Some(&tick.0) {
+        replay.next_restart += 1;
+        next_state.set(GameState::InGame);
+    }
+}
+
+// reads the events on the same tick the ship systems consume them
+fn record_inputs(
+    mut recording: ResMut<InputRecording>,
+    tick: Res<SimulationTick>,
+    controllers: Query<&PlayerController>,
+    mut accel_events: EventReader<Accelerate>,
+    mut accel_ang_events: EventReader<AccelerateAngular>,
+    mut shoot_events: EventReader<Shoot>,
+    mut hyperspace_events: EventReader<Hyperspace>,
+    mut shield_events: EventReader<RaiseShield>,
+    mut switch_events: EventReader<SwitchWeapon>,
+    mut missile_events: EventReader<LaunchMissile>,
+) {
+    let mut record = |controller: Entity, input: RecordedInput| {
+        if let Ok(controller) = controllers.get(controller) {
+            recording.events.push(RecordedEvent {
+                tick: tick.0,
+                controller: controller.id,
+                input,
+            });
+        }
+    };
+    for event in accel_events.read() {
+        let direction = (event.direction.x, event.direction.y);
+        record(event.controller, RecordedInput::Accelerate(direction));
+    }
+    for event in accel_ang_events.read() {
+        record(event.controller, RecordedInput::AccelerateAngular(event.direction));
+    }
+    for event in shoot_events.read() {
+        record(event.controller, RecordedInput::Shoot(event.held));
+    }
+    for event in hyperspace_events.read() {
+        record(event.controller, RecordedInput::Hyperspace);
+    }
+    for event in shield_events.read() {
+        record(event.controller, RecordedInput::RaiseShield(event.held));
+    }
+    for event in switch_events.read() {
+        record(event.controller, RecordedInput::SwitchWeapon);
+    }
+    for event in missile_events.read() {
+        record(event.controller, RecordedInput::LaunchMissile);
+    }
+}
+
+fn save_recording(recording: &InputRecording, path: &RecordPath) {
+    if let Err(error) = recording.save(&path.0) {
+        error!("couldn't save replay to {}: {}", path.0.display(), error);
+    }
+}
+
+// score is reset when the next game starts, so it is still the final one here
+fn record_game_over(
+    mut recording: ResMut<InputRecording>,
+    tick: Res<SimulationTick>,
+    score: Res<Score>,
+    path: Option<Res<RecordPath>>,
+) {
+    recording.game_over = Some(RecordedGameOver {
+        tick: tick.0,
+        score: score.score,
+    });
+    if let Some(path) = path {
+        save_recording(&recording, &path);
+    }
+}
+
+fn record_restart(mut recording: ResMut<InputRecording>, tick: Res<SimulationTick>) {
+    recording.restarts.push(tick.0);
+}
+
+fn save_on_exit(
+    recording: Res<InputRecording>,
+    path: Res<RecordPath>,
+    mut exit: EventReader<AppExit>,
+) {
+    if exit.read().next().is_some() {
+        save_recording(&recording, &path);
+    }
+}
+
+pub struct ReplayPlugin {
+    pub seed: u64,
+    pub tick_rate: f64,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+impl ReplayPlugin {
+    fn load_replay(&self, path: &Path) -> Result<InputRecording, String> {
+        let recording = InputRecording::load(path)?;
+        // the simulation only repeats itself under the same seed and tick rate
+        if recording.seed != self.seed || recording.tick_rate != self.tick_rate {
+            return Err(format!(
+                "recorded with seed {} at {} Hz, running seed {} at {} Hz",
+                recording.seed, recording.tick_rate, self.seed, self.tick_rate
+            ));
+        }
+        Ok(recording)
+    }
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        // always recorded, the path only decides whether it ends up on disk
+        app.insert_resource(InputRecording::new(self.seed, self.tick_rate));
+        app.add_systems(
+            FixedUpdate,
+            (record_inputs)
+                .after(advance_tick)
+                .in_set(InGameSet::ReplayInput),
+        );
+        app.add_systems(OnEnter(GameState::GameOver), record_game_over);
+        app.add_systems(OnExit(GameState::GameOver), record_restart);
+
+        if let Some(path) = &self.record {
+            app.insert_resource(RecordPath(path.clone()));
+            app.add_systems(Last, save_on_exit);
+        }
+
+        // a replay that can't be played still leaves a normal game to play
+        let recording = self.replay.as_ref().and_then(|path| {
+            self.load_replay(path)
+                .map_err(|error| error!("couldn't load replay {}: {}", path.display(), error))
+                .ok()
+        });
+        if let Some(recording) = recording {
+            app.insert_resource(InputReplay {
+                recording,
+                next: 0,
+                next_restart: 0,
+            });
+            app.add_systems(
+                Update,
+                (attach_replay_controllers).in_set(InGameSet::LoadEntities),
+            );
+            app.add_systems(
+                FixedUpdate,
+                (replay_inputs)
+                    .after(advance_tick)
+                    .before(record_inputs)
+                    .in_set(InGameSet::ReplayInput),
+            );
+            app.add_systems(
+                Update,
+                (replay_restarts)
+                    .in_set(InGameSet::MenuInput)
+                    .run_if(in_state(GameState::GameOver)),
+            );
+        }
+    }
+}
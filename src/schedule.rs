@@ -7,38 +7,67 @@ pub enum InGameSet {
     GameInput,
     MenuInput,
     LoadEntities,
+    ReplayInput,
     DespawnEntities,
+    AccumulateForces,
     UpdateEntities,
     CollisionDetection,
     CollisionReaction,
     RenderSetup,
 }
 
-pub struct SchudulePlugin;
+// number of fixed ticks simulated so far, replays are keyed on it
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationTick(pub u64);
+
+pub fn advance_tick(mut tick: ResMut<SimulationTick>) {
+    tick.0 += 1;
+}
+
+// a state change only lands at the start of the next frame, don't keep stepping ticks past it
+fn no_pending_transition(next_state: Res<NextState<GameState>>) -> bool {
+    matches!(*next_state, NextState::Unchanged)
+}
+
+// simulation sets step at a fixed rate so results don't depend on frame rate
+pub struct SchudulePlugin {
+    pub tick_rate: f64,
+}
 
 impl Plugin for SchudulePlugin {
     fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(self.tick_rate));
+        app.init_resource::<SimulationTick>();
+        // loaders are idempotent and also run while the game is still loading
+        app.configure_sets(Update, InGameSet::LoadEntities.before(InGameSet::GameInput));
         app.configure_sets(
             Update,
+            (InGameSet::GameInput, InGameSet::RenderSetup)
+                .chain()
+                .run_if(in_state(GameState::InGame)),
+        );
+        app.configure_sets(
+            FixedUpdate,
             (
-                InGameSet::LoadEntities,
+                InGameSet::ReplayInput,
                 InGameSet::DespawnEntities,
                 // apply_deferred(Flush)
-                InGameSet::GameInput,
+                InGameSet::AccumulateForces,
                 InGameSet::UpdateEntities,
                 InGameSet::CollisionDetection,
                 InGameSet::CollisionReaction,
-                InGameSet::RenderSetup,
             )
                 .chain()
-                .run_if(in_state(GameState::InGame)),
+                .run_if(in_state(GameState::InGame).and(no_pending_transition)),
         );
         app.add_systems(
-            Update,
+            FixedUpdate,
             apply_deferred
                 .after(InGameSet::DespawnEntities)
-                .before(InGameSet::GameInput),
+                .before(InGameSet::AccumulateForces),
         );
-        app.configure_sets(Update, (InGameSet::MenuInput));
+        app.add_systems(FixedUpdate, advance_tick.in_set(InGameSet::ReplayInput));
+        // a snapshot loaded from the menu is restored in the same frame
+        app.configure_sets(Update, (InGameSet::MenuInput).before(InGameSet::LoadEntities));
     }
 }
@@ -1,12 +1,14 @@
-use crate::{schedule::InGameSet, GameState};
+use crate::{schedule::InGameSet, GameState, Headless};
 use bevy::prelude::*;
 use bevy_common_assets::ron::RonAssetPlugin;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 // TODO! add teams to score
-#[derive(Resource, Default)]
-struct Score {
-    score: u16,
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Score {
+    pub score: u16,
+    // furthest wave reached, every point is worth this many
+    pub wave: u16,
 }
 
 #[derive(Asset, Default, Deserialize, Clone, Copy, TypePath)]
@@ -31,16 +33,19 @@ fn load_config(
 }
 
 #[derive(Event)]
-pub struct Scored;
+pub struct Scored {
+    pub points: u16,
+}
 
 fn update_score(mut score: ResMut<Score>, mut events: EventReader<Scored>) {
-    for _ in events.read() {
-        score.score += 1;
+    for event in events.read() {
+        let points = event.points.saturating_mul(score.wave.max(1));
+        score.score = score.score.saturating_add(points);
     }
 }
 
 fn reset_score(mut score: ResMut<Score>) {
-    score.score = 0;
+    *score = Score::default();
 }
 
 #[derive(Component)]
@@ -107,12 +112,13 @@ impl Plugin for ScorePlugin {
         app.add_plugins(RonAssetPlugin::<ScoreConfig>::new(&["score.ron"]));
         app.init_resource::<Score>();
         app.add_event::<Scored>();
-        app.add_systems(Startup, (load_config, spawn_playerscore));
-        app.add_systems(Update, (spawn_scoreboard).in_set(InGameSet::LoadEntities));
-        app.add_systems(
-            Update,
-            (update_score, update_scoreboard).in_set(InGameSet::UpdateEntities),
-        );
-        app.add_systems(OnEnter(GameState::GameOver), reset_score);
+        app.add_systems(FixedUpdate, (update_score).in_set(InGameSet::UpdateEntities));
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(Startup, (load_config, spawn_playerscore));
+            app.add_systems(Update, (spawn_scoreboard).in_set(InGameSet::LoadEntities));
+            app.add_systems(Update, (update_scoreboard).in_set(InGameSet::RenderSetup));
+        }
+        // keep the final score around until the next game starts
+        app.add_systems(OnExit(GameState::GameOver), reset_score);
     }
 }
@@ -1,14 +1,24 @@
 use crate::{
+    asteroid::{bounce_asteroids, destroy_asteroids, restore_asteroids, Asteroid, Exploded},
     bodies::*,
     bullet::CreateBullet,
     control::{Pawn, PlayerController, ShipPawn},
-    control_2d::{Accelerate, AccelerateAngular, Shoot},
+    control_2d::{
+        hold_controls, Accelerate, AccelerateAngular, HeldControls, Hyperspace, SwitchWeapon,
+    },
+    gravity::Swallowed,
+    grid::Grid,
+    missile::MissileLauncher,
     schedule::InGameSet,
-    GameState,
+    snapshot::{PendingSnapshot, RestoredEntities},
+    spawner::SpawnGenerator,
+    weapon::{WeaponConfig, Weapons},
+    GameState, Headless,
 };
 use bevy::prelude::*;
 use bevy_common_assets::ron::RonAssetPlugin;
-use serde::Deserialize;
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 #[derive(Deserialize, Asset, Clone, TypePath)]
@@ -19,9 +29,25 @@ struct ShipConfig {
     damping_angular: f32,
     mesh_path: String,
     color: (f32, f32, f32),
-    fire_delay: u64,
+    density: f32,
+    // dealt to an asteroid the ship flies into
+    damage: f32,
     fire_reload: u64,
     fire_magazine: u32,
+    lives: u32,
+    // ms after a death before the next ship may appear
+    respawn_delay: u64,
+    // no asteroid may be this close to the centre when respawning
+    respawn_clearance: f32,
+    invulnerability: u64,
+    hyperspace_cooldown: u64,
+    // chance between 0 and 1 that a jump destroys the ship instead
+    hyperspace_malfunction: f32,
+    // asteroid hits a full shield absorbs
+    shield_hits: u32,
+    // energy per second, a full shield holds 1.0
+    shield_drain: f32,
+    shield_recharge: f32,
 }
 
 #[derive(Resource)]
@@ -30,7 +56,12 @@ struct ShipConfigHandle {
 }
 
 #[derive(Resource)]
-struct ShipAsset {
+pub(crate) struct ShipAsset {
+    // missing when running headless
+    render: Option<ShipRender>,
+}
+
+struct ShipRender {
     mesh: Handle<Mesh>,
     material: Handle<ColorMaterial>,
 }
@@ -48,7 +79,7 @@ fn load_config(
 fn load_assets(
     asset_server: Res<AssetServer>,
     mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
     configs: Res<Assets<ShipConfig>>,
     config_handle: Res<ShipConfigHandle>,
     ship_asset: Option<Res<ShipAsset>>,
@@ -57,32 +88,106 @@ fn load_assets(
         return;
     }
     if let Some(config) = configs.get(config_handle.config.id()) {
-        let mesh: Handle<Mesh> = asset_server.load(
-            GltfAssetLabel::Primitive {
-                mesh: 0,
-                primitive: 0,
-            }
-            .from_asset(config.mesh_path.clone()),
-        );
-        let color = Color::srgb(config.color.0, config.color.1, config.color.2);
-        let material = materials.add(color);
-        commands.insert_resource(ShipAsset {
-            mesh,
-            material,
+        let render = materials.map(|mut materials| {
+            let mesh: Handle<Mesh> = asset_server.load(
+                GltfAssetLabel::Primitive {
+                    mesh: 0,
+                    primitive: 0,
+                }
+                .from_asset(config.mesh_path.clone()),
+            );
+            let color = Color::srgb(config.color.0, config.color.1, config.color.2);
+            let material = materials.add(color);
+            ShipRender { mesh, material }
         });
+        commands.insert_resource(ShipAsset { render });
     }
 }
 
+pub fn ships_loaded(ship_asset: Option<Res<ShipAsset>>) -> bool {
+    ship_asset.is_some()
+}
 
 #[derive(Component)]
 pub struct Ship;
 
+// rounds left before the ship has to reload, the hud reads it too
+#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Magazine {
+    pub rounds: u32,
+    pub capacity: u32,
+    // when the last round went, reloading is done fire_reload ms later
+    pub emptied: Duration,
+}
+
+impl Magazine {
+    pub fn full(capacity: u32) -> Self {
+        Self {
+            rounds: capacity,
+            capacity,
+            emptied: Duration::ZERO,
+        }
+    }
+}
+
+// kept on the controller, ships come and go
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Lives {
+    pub remaining: u32,
+}
+
+// only recharges while down, the hud reads it too
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Shield {
+    pub energy: f32,
+    pub up: bool,
+}
+
+// asteroids a raised shield is already holding off, they only cost energy when they first touch
+#[derive(Component, Clone, Debug, Default)]
+pub(crate) struct ShieldContacts(pub(crate) Vec<Entity>);
+
+// index into the weapon list
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CurrentWeapon {
+    pub index: usize,
+    // while the trigger is held on a weapon that needs charging
+    charge_start: Option<Duration>,
+}
+
+impl CurrentWeapon {
+    fn new(index: usize) -> Self {
+        Self {
+            index,
+            charge_start: None,
+        }
+    }
+}
+
+// none until the first jump, which is always allowed
+#[derive(Component, Default)]
+struct LastJump(Option<Duration>);
+
+// the jump went wrong, the ship is lost on the next tick like any other death
+#[derive(Event)]
+struct Malfunction {
+    entity: Entity,
+}
+
+// controller waiting for its next ship
+#[derive(Component)]
+pub(crate) struct Respawning {
+    pub(crate) since: Duration,
+}
+
 #[derive(Bundle)]
 struct ShipBundle {
     ship: Ship,
     pawn: ShipPawn,
     position: Position,
+    previous_position: PreviousPosition,
     rotation: Rotation,
+    previous_rotation: PreviousRotation,
     scale: Scale,
     velocity: Velocity,
     acceleration: Acceleration,
@@ -91,6 +196,10 @@ struct ShipBundle {
     angular_acceleration: AngularAcceleration,
     angular_damping: AngularDamping,
     last_shot: TimeStamp,
+    weapon: CurrentWeapon,
+    last_jump: LastJump,
+    launcher: MissileLauncher,
+    contacts: ShieldContacts,
     rigid_body: RigidBody,
     collider: Collider,
 }
@@ -101,7 +210,9 @@ impl ShipBundle {
             ship: Ship,
             pawn,
             position: Position(Vec2::new(x, y)),
+            previous_position: PreviousPosition(Vec2::new(x, y)),
             rotation: Rotation(0.0),
+            previous_rotation: PreviousRotation(0.0),
             scale: Scale(10.0),
             velocity: Velocity(Vec2::new(0., 0.)),
             acceleration: Acceleration(Vec2::new(0., 0.)),
@@ -110,11 +221,19 @@ impl ShipBundle {
             angular_acceleration: AngularAcceleration(0.0),
             angular_damping: AngularDamping(0.0),
             last_shot: TimeStamp(Duration::ZERO),
+            weapon: CurrentWeapon::new(0),
+            last_jump: LastJump::default(),
+            launcher: MissileLauncher::default(),
+            contacts: ShieldContacts::default(),
             rigid_body: RigidBody {
                 radius: 0.1,
                 mass: 2.0,
             },
-            collider: Collider { team: 1 },
+            collider: Collider {
+                team: 1,
+                membership: LAYER_SHIP,
+                filter: LAYER_ASTEROID,
+            },
         }
     }
 }
@@ -132,20 +251,143 @@ fn spawn_ship(
     ));
 }
 
+// a new game starts with a new controller, the old one would keep sending input under the same id
+fn despawn_controllers(mut commands: Commands, controllers: Query<Entity, With<PlayerController>>) {
+    for entity in controllers.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+// the controller with this id, or a new one when the game doesn't have it yet
+fn find_or_spawn_controller(
+    commands: &mut Commands,
+    known: &mut Vec<(u32, Entity)>,
+    id: u32,
+) -> Entity {
+    if let Some(&(_, entity)) = known.iter().find(|(known_id, _)| *known_id == id) {
+        return entity;
+    }
+    let entity = commands.spawn(PlayerController { id }).id();
+    known.push((id, entity));
+    entity
+}
+
+// controllers outlive their ships, hand the saved ship back to the one with the same id
+pub(crate) fn restore_ships(
+    mut commands: Commands,
+    pending: Res<PendingSnapshot>,
+    mut restored: ResMut<RestoredEntities>,
+    ships: Query<Entity, With<Ship>>,
+    controllers: Query<(Entity, &PlayerController)>,
+    time: Res<Time<Fixed>>,
+) {
+    for entity in ships.iter() {
+        commands.entity(entity).despawn();
+    }
+    let mut known: Vec<(u32, Entity)> = controllers
+        .iter()
+        .map(|(entity, controller)| (controller.id, entity))
+        .collect();
+    for saved in pending.0.controllers.iter() {
+        let entity = find_or_spawn_controller(&mut commands, &mut known, saved.id);
+        let mut controller = commands.entity(entity);
+        controller.insert(Lives {
+            remaining: saved.lives,
+        });
+        match saved.respawning {
+            Some(since) => controller.insert(Respawning {
+                since: pending.restamp(since, time.elapsed()).0,
+            }),
+            None => controller.remove::<Respawning>(),
+        };
+    }
+    restored.ships.clear();
+    for saved in pending.0.ships.iter() {
+        let player_entity = find_or_spawn_controller(&mut commands, &mut known, saved.controller);
+        let position = saved.body.position.0;
+        let mut ship = commands.spawn((
+            ShipBundle::new(position.x, position.y, ShipPawn::new(player_entity)),
+            NeedsConfig,
+            Transform::default(),
+        ));
+        saved.body.restore(&mut ship);
+        ship.insert((
+            pending.restamp(saved.last_shot, time.elapsed()),
+            CurrentWeapon::new(saved.weapon),
+            Magazine {
+                emptied: pending.restamp(TimeStamp(saved.magazine.emptied), time.elapsed()).0,
+                ..saved.magazine
+            },
+        ));
+        // raised again on the next tick if the shield is still held
+        ship.insert((
+            Shield {
+                energy: saved.shield,
+                up: false,
+            },
+            MissileLauncher {
+                last_launch: saved
+                    .last_launch
+                    .map(|launched| pending.restamp(launched, time.elapsed()).0),
+            },
+        ));
+        let contacts = saved
+            .contacts
+            .iter()
+            .filter_map(|&index| restored.asteroids.get(index).copied().flatten())
+            .collect();
+        ship.insert(ShieldContacts(contacts));
+        if let Some(until) = saved.invulnerable {
+            ship.insert(Invulnerable {
+                until: pending.restamp(until, time.elapsed()).0,
+            });
+        }
+        restored.ships.push(ship.id());
+    }
+}
+
+type UnconfiguredShip = (
+    Entity,
+    &'static mut Damping,
+    &'static mut AngularDamping,
+    &'static mut RigidBody,
+    &'static ShipPawn,
+);
+
 fn add_config(
     mut commands: Commands,
-    mut ships: Query<(Entity, &mut Damping, &mut AngularDamping, &mut NeedsConfig), With<Ship>>,
+    mut ships: Query<UnconfiguredShip, (With<Ship>, With<NeedsConfig>)>,
+    lives: Query<(), With<Lives>>,
     ship_assets: Option<Res<ShipAsset>>,
     configs: Res<Assets<ShipConfig>>,
     config_handle: Res<ShipConfigHandle>,
 ) {
     if let Some(assets) = ship_assets {
         if let Some(config) = configs.get(config_handle.config.id()) {
-            for (entity, mut damping, mut angular_damping, _) in ships.iter_mut() {
+            for (entity, mut damping, mut angular_damping, mut rigid_body, pawn) in ships.iter_mut() {
+                // first ship of the game
+                let controller = *pawn.get_controller();
+                if !lives.contains(controller) {
+                    commands.entity(controller).insert(Lives {
+                        remaining: config.lives,
+                    });
+                }
                 damping.0 = config.damping;
                 angular_damping.0 = config.damping_angular;
-                commands.entity(entity).insert(Mesh2d(assets.mesh.clone()));
-                commands.entity(entity).insert(MeshMaterial2d(assets.material.clone()));
+                *rigid_body = RigidBody::from_density(rigid_body.radius, config.density);
+                // a restored ship already has its magazine and shield
+                commands.entity(entity).insert_if_new((
+                    Magazine::full(config.fire_magazine),
+                    Shield {
+                        energy: 1.0,
+                        up: false,
+                    },
+                ));
+                commands.entity(entity).insert(Damage(config.damage));
+                if let Some(render) = &assets.render {
+                    commands.entity(entity).insert(Mesh2d(render.mesh.clone()));
+                    commands.entity(entity).insert(MeshMaterial2d(render.material.clone()));
+                }
                 commands.entity(entity).remove::<NeedsConfig>();
             }
         }
@@ -186,77 +428,571 @@ fn apply_accel_ang(
     }
 }
 
-//TODO! add magazine
+fn switch_weapon(
+    mut ships: Query<(&mut CurrentWeapon, &ShipPawn), With<Ship>>,
+    mut events: EventReader<SwitchWeapon>,
+    weapons: Res<Weapons>,
+) {
+    for event in events.read() {
+        for (mut weapon, pawn) in ships.iter_mut() {
+            if pawn.get_controller() == &event.controller {
+                *weapon = CurrentWeapon::new((weapon.index + 1) % weapons.len());
+            }
+        }
+    }
+}
+
+type Gun = (
+    Entity,
+    &'static Position,
+    &'static Rotation,
+    &'static Velocity,
+    &'static RigidBody,
+    &'static Collider,
+    &'static mut TimeStamp,
+    &'static mut Magazine,
+    &'static mut CurrentWeapon,
+    &'static ShipPawn,
+);
+
+// every bullet takes a round, a spread only fans out what is left in the magazine
 fn shoot(
+    time: Res<Time>,
+    mut ships: Query<Gun, With<Ship>>,
+    controllers: Query<&HeldControls>,
+    mut create_bullet: EventWriter<CreateBullet>,
+    weapons: Res<Weapons>,
+    configs: Res<Assets<WeaponConfig>>,
+) {
+    for (
+        entity,
+        position,
+        rotation,
+        velocity,
+        rigid_body,
+        collider,
+        mut last_shot_time,
+        mut magazine,
+        mut weapon,
+        pawn,
+    ) in ships.iter_mut()
+    {
+        let Some(config) = weapons.get(&configs, weapon.index) else {
+            continue;
+        };
+        // letting go of the trigger loses the charge
+        let firing = controllers
+            .get(*pawn.get_controller())
+            .is_ok_and(|held| held.firing());
+        if !firing {
+            weapon.charge_start = None;
+            continue;
+        }
+        let time_elapsed = time.elapsed();
+        let charge_start = *weapon.charge_start.get_or_insert(time_elapsed);
+        if time_elapsed - charge_start < Duration::from_millis(config.charge)
+            || magazine.rounds == 0
+            || time_elapsed - last_shot_time.0 <= Duration::from_millis(config.fire_delay)
+        {
+            continue;
+        }
+        let nose = position.0 + Rot2::radians(rotation.0) * Vec2::new(0.0, rigid_body.radius);
+        let bullets = config.bullets.min(magazine.rounds);
+        for bullet in 0..bullets {
+            let offset = if bullets > 1 {
+                config.spread * (bullet as f32 / (bullets - 1) as f32 - 0.5)
+            } else {
+                0.0
+            };
+            create_bullet.send(CreateBullet {
+                shooter: entity,
+                team: collider.team,
+                position: nose,
+                rotation: rotation.0 + offset,
+                velocity: velocity.0,
+                weapon: weapon.index,
+            });
+        }
+        last_shot_time.0 = time_elapsed;
+        weapon.charge_start = None;
+        magazine.rounds -= bullets;
+        if magazine.rounds == 0 {
+            magazine.emptied = time_elapsed;
+        }
+    }
+}
+
+fn reload(
+    time: Res<Time>,
+    mut magazines: Query<&mut Magazine, With<Ship>>,
+    configs: Res<Assets<ShipConfig>>,
+    config_handle: Res<ShipConfigHandle>,
+) {
+    if let Some(config) = configs.get(config_handle.config.id()) {
+        for mut magazine in magazines.iter_mut() {
+            if magazine.rounds == 0
+                && time.elapsed() - magazine.emptied > Duration::from_millis(config.fire_reload)
+            {
+                magazine.rounds = magazine.capacity;
+            }
+        }
+    }
+}
+
+// reappear anywhere on screen, or blow up trying
+fn hyperspace(
     time: Res<Time>,
     mut ships: Query<
         (
-            &Position,
-            &Rotation,
-            &mut TimeStamp,
+            Entity,
+            &mut Position,
+            &mut PreviousPosition,
+            &mut LastJump,
             &ShipPawn
         ),
         With<Ship>,
     >,
-    mut events: EventReader<Shoot>,
-    mut create_bullet: EventWriter<CreateBullet>,
+    mut events: EventReader<Hyperspace>,
+    mut malfunctions: EventWriter<Malfunction>,
+    mut spawner: ResMut<SpawnGenerator>,
+    grid: Res<Grid>,
     configs: Res<Assets<ShipConfig>>,
     config_handle: Res<ShipConfigHandle>,
 ) {
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
+    };
     for event in events.read() {
-        for (position, rotation, mut last_shot_time, pawn) in ships.iter_mut() {
-            if let Some(config) = configs.get(config_handle.config.id()) {
-                if pawn.get_controller() == &event.controller {
-                    let time_elapsed = time.elapsed();
-                    if time_elapsed - last_shot_time.0 > Duration::from_millis(config.fire_delay) {
-                        create_bullet.send(CreateBullet {
-                            position: position.0,
-                            rotation: rotation.0,
-                        });
-                        last_shot_time.0 = time_elapsed;
-                    }
-                }
+        for (entity, mut position, mut previous, mut last_jump, pawn) in ships.iter_mut() {
+            let cooling = last_jump.0.is_some_and(|jumped| {
+                time.elapsed() - jumped <= Duration::from_millis(config.hyperspace_cooldown)
+            });
+            if pawn.get_controller() != &event.controller || cooling {
+                continue;
             }
+            last_jump.0 = Some(time.elapsed());
+            if spawner.rng.f32() < config.hyperspace_malfunction {
+                malfunctions.send(Malfunction { entity });
+                continue;
+            }
+            position.0 = Vec2::new(
+                spawner.rng.f32_normalized() * grid.width_half,
+                spawner.rng.f32_normalized() * grid.height_half,
+            );
+            // no interpolating or sweeping across the whole screen
+            previous.0 = position.0;
         }
     }
 }
 
-fn collisions_ship(
+fn shield(
+    time: Res<Time>,
+    mut ships: Query<(&mut Shield, &ShipPawn), With<Ship>>,
+    controllers: Query<&HeldControls>,
+    configs: Res<Assets<ShipConfig>>,
+    config_handle: Res<ShipConfigHandle>,
+) {
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
+    };
+    for (mut shield, pawn) in ships.iter_mut() {
+        let raised = controllers
+            .get(*pawn.get_controller())
+            .is_ok_and(|held| held.shielding());
+        shield.up = shield.energy > 0.0 && raised;
+        let rate = if shield.up {
+            -config.shield_drain
+        } else {
+            config.shield_recharge
+        };
+        shield.energy = (shield.energy + rate * time.delta_secs()).clamp(0.0, 1.0);
+    }
+}
+
+// the asteroid bounces off a raised shield, the ship takes the hit in lose_ships
+fn bounce_shields(
+    mut bodies: Query<(
+        &mut Position,
+        &mut Velocity,
+        &mut AngularVelocity,
+        &RigidBody,
+        Option<&PhysicsMaterial>,
+    )>,
+    shields: Query<&Shield, With<Ship>>,
+    asteroids: Query<(), With<Asteroid>>,
+    mut collisions: EventReader<Collision>,
+) {
+    for event in collisions.read() {
+        let (ship, asteroid, normal) = if asteroids.contains(event.entity2) {
+            (event.entity1, event.entity2, event.dir.normalize())
+        } else {
+            (event.entity2, event.entity1, -event.dir.normalize())
+        };
+        if !shields.get(ship).is_ok_and(|shield| shield.up) || !asteroids.contains(asteroid) {
+            continue;
+        }
+        if let Ok(
+            [(mut ship_pos, mut ship_vel, mut ship_ang, ship_body, ship_mat), (mut ast_pos, mut ast_vel, mut ast_ang, ast_body, ast_mat)],
+        ) = bodies.get_many_mut([ship, asteroid])
+        {
+            // the shield is a disc around the ship
+            ((ship_vel.0, ship_ang.0), (ast_vel.0, ast_ang.0)) = collision_bounce(
+                &ImpactBody {
+                    velocity: ship_vel.0,
+                    angular_velocity: ship_ang.0,
+                    mass: ship_body.mass,
+                    inertia: ship_body.inertia(),
+                    offset: normal * ship_body.radius,
+                    material: ship_mat.copied().unwrap_or_default(),
+                },
+                &ImpactBody {
+                    velocity: ast_vel.0,
+                    angular_velocity: ast_ang.0,
+                    mass: ast_body.mass,
+                    inertia: ast_body.inertia(),
+                    offset: -normal * ast_body.radius,
+                    material: ast_mat.copied().unwrap_or_default(),
+                },
+                normal,
+            );
+
+            let depth = event.collide_dist - event.dist;
+            let correction = normal * (depth * 0.8);
+            ship_pos.0 -= correction;
+            ast_pos.0 += correction;
+        }
+    }
+}
+
+type Vulnerable = (With<Ship>, Without<Invulnerable>);
+
+// collider filters already drop pairs the ship shouldn't hit.
+// a ship can be hit several times in one tick, it only dies once
+fn lose_ships(
     mut commands: Commands,
-    ships: Query<(Entity, &Collider), With<Ship>>,
-    colliders: Query<(Entity, &Collider)>,
+    time: Res<Time>,
+    mut ships: Query<(Entity, &ShipPawn, Option<&mut Shield>, &mut ShieldContacts), With<Ship>>,
+    bodies: Query<(Entity, &Position, &RigidBody), Vulnerable>,
+    mut lives: Query<&mut Lives>,
+    grid: Res<Grid>,
+    configs: Res<Assets<ShipConfig>>,
+    config_handle: Res<ShipConfigHandle>,
     mut collisions: EventReader<Collision>,
+    mut explosions: EventReader<Exploded>,
+    mut swallowed: EventReader<Swallowed>,
+    mut malfunctions: EventReader<Malfunction>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
+    // every ship with what touched it, overlapping contacts send the same pair more than once
+    let mut touched: Vec<(Entity, Option<Entity>)> = Vec::new();
     for event in collisions.read() {
-        for (entity_a, entity_b) in [
+        for (ship, other) in [
             (event.entity1, event.entity2),
             (event.entity2, event.entity1),
         ] {
-            if let Ok((ship, ship_collider)) = ships.get(entity_a) {
-                if let Ok((_, collider)) = colliders.get(entity_b) {
-                    if collider.team != ship_collider.team {
-                        commands.entity(ship).despawn();
-                        next_state.set(GameState::GameOver);
-                    }
+            if ships.contains(ship) && !touched.contains(&(ship, Some(other))) {
+                touched.push((ship, Some(other)));
+            }
+        }
+    }
+    // caught in the blast of an explosive asteroid, there is nothing left to stay in contact with
+    for explosion in explosions.read() {
+        for (entity, position, body) in bodies.iter() {
+            if grid.wrapped_delta(explosion.position, position.0).length()
+                < explosion.radius + body.radius
+            {
+                touched.push((entity, None));
+            }
+        }
+    }
+    let hits = configs
+        .get(config_handle.config.id())
+        .map_or(1, |config| config.shield_hits.max(1));
+    let mut lost = Vec::new();
+    for (entity, _, shield, mut contacts) in ships.iter_mut() {
+        let touching = touched.iter().filter(|(ship, _)| *ship == entity);
+        match shield {
+            // a raised shield takes every hit of the tick, even the ones that wear it out
+            Some(mut shield) if shield.up => {
+                let fresh = touching
+                    .clone()
+                    .filter(|(_, other)| other.is_none_or(|other| !contacts.0.contains(&other)))
+                    .count();
+                if fresh > 0 {
+                    shield.energy = (shield.energy - fresh as f32 / hits as f32).max(0.0);
+                    shield.up = shield.energy > 0.0;
                 }
+                let current: Vec<Entity> = touching.filter_map(|(_, other)| *other).collect();
+                if contacts.0 != current {
+                    contacts.0 = current;
+                }
+            }
+            _ => {
+                if touching.count() > 0 {
+                    lost.push(entity);
+                }
+                if !contacts.0.is_empty() {
+                    contacts.0.clear();
+                }
+            }
+        }
+    }
+    lost.extend(swallowed.read().map(|event| event.entity));
+    lost.extend(malfunctions.read().map(|event| event.entity));
+    let mut dead = Vec::new();
+    for entity in lost {
+        if dead.contains(&entity) {
+            continue;
+        }
+        let Ok((_, pawn, ..)) = ships.get(entity) else {
+            continue;
+        };
+        dead.push(entity);
+        commands.entity(entity).despawn();
+        let controller = *pawn.get_controller();
+        match lives.get_mut(controller) {
+            Ok(mut lives) if lives.remaining > 1 => {
+                lives.remaining -= 1;
+                commands.entity(controller).insert(Respawning {
+                    since: time.elapsed(),
+                });
+            }
+            Ok(mut lives) => {
+                lives.remaining = 0;
+                next_state.set(GameState::GameOver);
+            }
+            Err(_) => next_state.set(GameState::GameOver),
+        }
+    }
+}
+
+// back in the centre once the delay is up and nothing is parked there
+fn respawn_ships(
+    mut commands: Commands,
+    time: Res<Time>,
+    controllers: Query<(Entity, &Respawning)>,
+    asteroids: Query<(&Position, &RigidBody), With<Asteroid>>,
+    grid: Res<Grid>,
+    configs: Res<Assets<ShipConfig>>,
+    config_handle: Res<ShipConfigHandle>,
+) {
+    if let Some(config) = configs.get(config_handle.config.id()) {
+        let blocked = asteroids.iter().any(|(position, body)| {
+            grid.wrapped_delta(Vec2::ZERO, position.0).length()
+                < config.respawn_clearance + body.radius
+        });
+        if blocked {
+            return;
+        }
+        for (controller, respawning) in controllers.iter() {
+            if time.elapsed() - respawning.since < Duration::from_millis(config.respawn_delay) {
+                continue;
             }
+            commands.entity(controller).remove::<Respawning>();
+            commands.spawn((
+                ShipBundle::new(0., 0., ShipPawn::new(controller)),
+                NeedsConfig,
+                Transform::default(),
+                Invulnerable {
+                    until: time.elapsed() + Duration::from_millis(config.invulnerability),
+                },
+            ));
         }
     }
 }
 
+fn expire_invulnerability(
+    mut commands: Commands,
+    time: Res<Time>,
+    ships: Query<(Entity, &Invulnerable), With<Ship>>,
+) {
+    for (entity, invulnerable) in ships.iter() {
+        if time.elapsed() >= invulnerable.until {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+const BLINK_PERIOD: f32 = 0.2;
+
+fn blink_invulnerable(
+    time: Res<Time>,
+    mut ships: Query<(&mut Visibility, Has<Invulnerable>), With<Ship>>,
+) {
+    for (mut visibility, invulnerable) in ships.iter_mut() {
+        let hidden = invulnerable && (time.elapsed_secs() / BLINK_PERIOD) as u32 % 2 == 1;
+        visibility.set_if_neq(if hidden {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
+    }
+}
+
 pub struct ShipPlugin;
 
 impl Plugin for ShipPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(RonAssetPlugin::<ShipConfig>::new(&["ship.ron"]));
+        app.add_event::<Malfunction>();
         app.add_systems(Startup, (load_config, spawn_ship));
-        app.add_systems(OnExit(GameState::GameOver), spawn_ship);
+        app.add_systems(
+            OnExit(GameState::GameOver),
+            (despawn_controllers, spawn_ship).chain(),
+        );
         app.add_systems(Update, (load_assets, add_config ).in_set(InGameSet::LoadEntities));
         app.add_systems(
             Update,
-            (apply_accel, apply_accel_ang, shoot).in_set(InGameSet::UpdateEntities),
+            // configured in the same frame, before a tick sees the ship undamped
+            (restore_ships)
+                .after_ignore_deferred(restore_asteroids)
+                .before(add_config)
+                .in_set(InGameSet::LoadEntities)
+                .run_if(resource_exists::<PendingSnapshot>),
+        );
+        // controls set the accelerations the bodies integrate this tick
+        app.add_systems(
+            FixedUpdate,
+            (
+                apply_accel,
+                apply_accel_ang,
+                (reload, switch_weapon, shoot).chain().after(hold_controls),
+                hyperspace,
+                (shield).after(hold_controls),
+            )
+                .in_set(InGameSet::AccumulateForces),
         );
-        app.add_systems(Update, (collisions_ship).in_set(InGameSet::DespawnEntities));
+        app.add_systems(
+            FixedUpdate,
+            // lose_ships lowers shields the asteroids still have to see
+            (lose_ships, respawn_ships, expire_invulnerability)
+                .chain()
+                .after(destroy_asteroids)
+                .in_set(InGameSet::DespawnEntities),
+        );
+        // moves asteroids too, so it has to wait for their own bounces
+        app.add_systems(
+            FixedUpdate,
+            (bounce_shields)
+                .after(bounce_asteroids)
+                .in_set(InGameSet::CollisionReaction),
+        );
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(Update, (blink_invulnerable).in_set(InGameSet::RenderSetup));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::state::app::StatesPlugin;
+
+    fn test_config() -> ShipConfig {
+        ShipConfig {
+            speed: 1.0,
+            damping: 0.0,
+            speed_angular: 1.0,
+            damping_angular: 0.0,
+            mesh_path: String::new(),
+            color: (1.0, 1.0, 1.0),
+            density: 1.0,
+            damage: 1.0,
+            fire_reload: 0,
+            fire_magazine: 1,
+            lives: 3,
+            respawn_delay: 0,
+            respawn_clearance: 0.0,
+            invulnerability: 0,
+            hyperspace_cooldown: 0,
+            hyperspace_malfunction: 0.0,
+            shield_hits: 3,
+            shield_drain: 0.0,
+            shield_recharge: 0.0,
+        }
+    }
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(StatesPlugin);
+        app.init_state::<GameState>();
+        app.add_event::<Collision>();
+        app.add_event::<Exploded>();
+        app.add_event::<Swallowed>();
+        app.add_event::<Malfunction>();
+        app.init_resource::<Time>();
+        app.insert_resource(Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        });
+        let mut configs = Assets::<ShipConfig>::default();
+        let handle = configs.add(test_config());
+        app.insert_resource(configs);
+        app.insert_resource(ShipConfigHandle { config: handle });
+        app.add_systems(Update, lose_ships);
+        app
+    }
+
+    fn shielded_ship(app: &mut App, energy: f32) -> Entity {
+        let controller = app.world_mut().spawn(Lives { remaining: 3 }).id();
+        app.world_mut()
+            .spawn((
+                ShipBundle::new(0.0, 0.0, ShipPawn::new(controller)),
+                Shield { energy, up: true },
+            ))
+            .id()
+    }
+
+    fn touch(app: &mut App, ship: Entity, asteroids: &[Entity]) {
+        for &asteroid in asteroids {
+            app.world_mut().send_event(Collision {
+                entity1: ship,
+                entity2: asteroid,
+                dir: Vec2::X,
+                dist: 0.0,
+                collide_dist: 0.1,
+            });
+        }
+        app.update();
+    }
+
+    fn energy(app: &App, ship: Entity) -> f32 {
+        app.world().get::<Shield>(ship).unwrap().energy
+    }
+
+    // the bounce only pushes it part of the way out, staying in contact is still one hit
+    #[test]
+    fn lingering_asteroid_costs_one_hit() {
+        let mut app = test_app();
+        let ship = shielded_ship(&mut app, 1.0);
+        let asteroid = app.world_mut().spawn_empty().id();
+        for _ in 0..4 {
+            // overlapping contacts of the same pair
+            touch(&mut app, ship, &[asteroid, asteroid]);
+        }
+        assert!((energy(&app, ship) - 2.0 / 3.0).abs() < 1e-5);
+
+        // leaving and coming back is a new hit
+        touch(&mut app, ship, &[]);
+        touch(&mut app, ship, &[asteroid]);
+        assert!((energy(&app, ship) - 1.0 / 3.0).abs() < 1e-5);
+    }
+
+    // the shield holds for the tick it wears out in, the ship only goes on the next hit
+    #[test]
+    fn shield_takes_the_whole_tick() {
+        let mut app = test_app();
+        let ship = shielded_ship(&mut app, 1.0 / 3.0);
+        let first = app.world_mut().spawn_empty().id();
+        let second = app.world_mut().spawn_empty().id();
+        touch(&mut app, ship, &[first, second]);
+        let shield = app.world().get::<Shield>(ship).unwrap();
+        assert_eq!(shield.energy, 0.0);
+        assert!(!shield.up);
+
+        touch(&mut app, ship, &[first]);
+        assert!(app.world().get_entity(ship).is_err());
     }
 }
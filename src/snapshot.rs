@@ -0,0 +1,411 @@
+use crate::{
+    asteroid::{Asteroid, AsteroidKind, AsteroidVariant},
+    bodies::*,
+    bullet::{Bullet, HitList, Owner},
+    control::{Pawn, PlayerController, ShipPawn},
+    missile::{Missile, MissileLauncher},
+    schedule::{InGameSet, SimulationTick},
+    score::Score,
+    ship::{CurrentWeapon, Lives, Magazine, Respawning, Shield, ShieldContacts, Ship},
+    spawner::SpawnGenerator,
+    wave::Wave,
+    GameState, Headless,
+};
+use bevy::prelude::*;
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// bump whenever old snapshots can no longer be loaded
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const QUICKSAVE_PATH: &str = "quicksave.snapshot.ron";
+
+// simulation state every saved body carries
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct BodySnapshot {
+    pub position: Position,
+    pub velocity: Velocity,
+    pub rotation: Rotation,
+    pub angular_velocity: AngularVelocity,
+    pub scale: Scale,
+    pub rigid_body: RigidBody,
+    pub collider: Collider,
+}
+
+type BodyQuery = (
+    &'static Position,
+    &'static Velocity,
+    &'static Rotation,
+    &'static AngularVelocity,
+    &'static Scale,
+    &'static RigidBody,
+    &'static Collider,
+);
+
+impl BodySnapshot {
+    fn capture(
+        (position, velocity, rotation, angular_velocity, scale, rigid_body, collider): (
+            &Position,
+            &Velocity,
+            &Rotation,
+            &AngularVelocity,
+            &Scale,
+            &RigidBody,
+            &Collider,
+        ),
+    ) -> Self {
+        Self {
+            position: *position,
+            velocity: *velocity,
+            rotation: *rotation,
+            angular_velocity: *angular_velocity,
+            scale: *scale,
+            rigid_body: *rigid_body,
+            collider: *collider,
+        }
+    }
+
+    // overwrite whatever the bundle worked out with the saved state
+    pub fn restore(&self, entity: &mut EntityCommands) {
+        entity.insert((
+            self.position,
+            PreviousPosition(self.position.0),
+            self.velocity,
+            self.rotation,
+            PreviousRotation(self.rotation.0),
+            self.angular_velocity,
+            self.scale,
+            self.rigid_body,
+            self.collider,
+        ));
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShipSnapshot {
+    pub body: BodySnapshot,
+    // PlayerController id, entities aren't stable between runs
+    pub controller: u32,
+    pub last_shot: TimeStamp,
+    pub weapon: usize,
+    pub magazine: Magazine,
+    // shield energy, 1.0 is full
+    pub shield: f32,
+    // indices into the saved asteroids already touching the raised shield
+    pub contacts: Vec<usize>,
+    pub last_launch: Option<TimeStamp>,
+    // end of the grace period after a respawn
+    pub invulnerable: Option<TimeStamp>,
+}
+
+// lives are kept on the controller, so they are saved while no ship is around too
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ControllerSnapshot {
+    pub id: u32,
+    pub lives: u32,
+    // when the last ship was lost, while waiting for the next one
+    pub respawning: Option<TimeStamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct AsteroidSnapshot {
+    pub body: BodySnapshot,
+    pub variant: usize,
+    pub kind: usize,
+    pub health: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulletSnapshot {
+    pub body: BodySnapshot,
+    pub spawn_time: TimeStamp,
+    pub weapon: usize,
+    pub pierce: u32,
+    // indices into the saved asteroids
+    pub hits: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct MissileSnapshot {
+    pub body: BodySnapshot,
+    pub spawn_time: TimeStamp,
+    pub fuel: f32,
+    // indices into the saved asteroids and ships
+    pub target: Option<usize>,
+    pub owner: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub tick: u64,
+    // fixed clock the timestamps were taken on
+    pub elapsed: Duration,
+    pub score: Score,
+    pub wave: Wave,
+    pub spawner: RngComponent,
+    pub controllers: Vec<ControllerSnapshot>,
+    pub ships: Vec<ShipSnapshot>,
+    pub asteroids: Vec<AsteroidSnapshot>,
+    pub bullets: Vec<BulletSnapshot>,
+    pub missiles: Vec<MissileSnapshot>,
+}
+
+// only the header, so a version mismatch is reported before the rest fails to parse
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())?;
+        fs::write(path, text).map_err(|error| error.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let header: SnapshotHeader = ron::from_str(&text).map_err(|error| error.to_string())?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "snapshot version {} is not supported, expected {}",
+                header.version, SNAPSHOT_VERSION
+            ));
+        }
+        ron::from_str(&text).map_err(|error| error.to_string())
+    }
+}
+
+#[derive(Event)]
+pub struct SaveSnapshot {
+    pub path: PathBuf,
+}
+
+#[derive(Event)]
+pub struct LoadSnapshot {
+    pub path: PathBuf,
+}
+
+// saved index to the entity restored from it, saved bodies point at each other by index.
+// None where the saved body couldn't be restored
+#[derive(Resource, Default)]
+pub struct RestoredEntities {
+    pub ships: Vec<Entity>,
+    pub asteroids: Vec<Option<Entity>>,
+}
+
+// each plugin rebuilds its own bodies from it, gone again at the end of the frame
+#[derive(Resource)]
+pub struct PendingSnapshot(pub Snapshot);
+
+impl PendingSnapshot {
+    // shift by however far the fixed clock has run since the save
+    pub fn restamp(&self, stamp: TimeStamp, now: Duration) -> TimeStamp {
+        TimeStamp(stamp.0 + now.saturating_sub(self.0.elapsed))
+    }
+}
+
+type SavedShip = (
+    Entity,
+    BodyQuery,
+    &'static TimeStamp,
+    &'static CurrentWeapon,
+    &'static Magazine,
+    &'static Shield,
+    &'static ShieldContacts,
+    &'static MissileLauncher,
+    Option<&'static Invulnerable>,
+    &'static ShipPawn,
+);
+
+fn save_snapshot(
+    mut events: EventReader<SaveSnapshot>,
+    ships: Query<SavedShip, With<Ship>>,
+    asteroids: Query<
+        (Entity, BodyQuery, &AsteroidVariant, &AsteroidKind, &Health),
+        With<Asteroid>,
+    >,
+    bullets: Query<(BodyQuery, &TimeStamp, &Bullet, &HitList)>,
+    missiles: Query<(BodyQuery, &TimeStamp, &Missile, Option<&Owner>)>,
+    controllers: Query<(&PlayerController, Option<&Lives>, Option<&Respawning>)>,
+    score: Res<Score>,
+    wave: Res<Wave>,
+    spawner: Res<SpawnGenerator>,
+    tick: Res<SimulationTick>,
+    time: Res<Time<Fixed>>,
+) {
+    for event in events.read() {
+        let asteroid_entities: Vec<Entity> = asteroids.iter().map(|(entity, ..)| entity).collect();
+        let asteroid_index =
+            |entity: Entity| asteroid_entities.iter().position(|asteroid| *asteroid == entity);
+        let saved_ships: Vec<(Entity, ShipSnapshot)> = ships
+            .iter()
+            .filter_map(
+                |(
+                    entity,
+                    body,
+                    last_shot,
+                    weapon,
+                    magazine,
+                    shield,
+                    contacts,
+                    launcher,
+                    invulnerable,
+                    pawn,
+                )| {
+                    let (controller, ..) = controllers.get(*pawn.get_controller()).ok()?;
+                    let ship = ShipSnapshot {
+                        body: BodySnapshot::capture(body),
+                        controller: controller.id,
+                        last_shot: *last_shot,
+                        weapon: weapon.index,
+                        magazine: *magazine,
+                        shield: shield.energy,
+                        contacts: contacts
+                            .0
+                            .iter()
+                            .filter_map(|&contact| asteroid_index(contact))
+                            .collect(),
+                        last_launch: launcher.last_launch.map(TimeStamp),
+                        invulnerable: invulnerable.map(|invulnerable| TimeStamp(invulnerable.until)),
+                    };
+                    Some((entity, ship))
+                },
+            )
+            .collect();
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            tick: tick.0,
+            elapsed: time.elapsed(),
+            score: *score,
+            wave: *wave,
+            spawner: spawner.rng.clone(),
+            controllers: controllers
+                .iter()
+                .filter_map(|(controller, lives, respawning)| {
+                    Some(ControllerSnapshot {
+                        id: controller.id,
+                        lives: lives?.remaining,
+                        respawning: respawning.map(|respawning| TimeStamp(respawning.since)),
+                    })
+                })
+                .collect(),
+            ships: saved_ships.iter().map(|(_, ship)| ship.clone()).collect(),
+            asteroids: asteroids
+                .iter()
+                .map(|(_, body, variant, kind, health)| AsteroidSnapshot {
+                    body: BodySnapshot::capture(body),
+                    variant: variant.0,
+                    kind: kind.0,
+                    health: health.current,
+                })
+                .collect(),
+            bullets: bullets
+                .iter()
+                .map(|(body, spawn_time, bullet, hit_list)| BulletSnapshot {
+                    body: BodySnapshot::capture(body),
+                    spawn_time: *spawn_time,
+                    weapon: bullet.weapon,
+                    pierce: bullet.pierce,
+                    hits: hit_list.0.iter().filter_map(|&hit| asteroid_index(hit)).collect(),
+                })
+                .collect(),
+            missiles: missiles
+                .iter()
+                .map(|(body, spawn_time, missile, owner)| MissileSnapshot {
+                    body: BodySnapshot::capture(body),
+                    spawn_time: *spawn_time,
+                    fuel: missile.fuel,
+                    target: missile.target.and_then(asteroid_index),
+                    owner: owner.and_then(|owner| {
+                        saved_ships.iter().position(|(entity, _)| *entity == owner.shooter)
+                    }),
+                })
+                .collect(),
+        };
+        match snapshot.save(&event.path) {
+            Ok(()) => info!("saved snapshot to {}", event.path.display()),
+            Err(error) => error!("couldn't save snapshot to {}: {}", event.path.display(), error),
+        }
+    }
+}
+
+fn load_snapshot(
+    mut commands: Commands,
+    mut events: EventReader<LoadSnapshot>,
+    mut time: ResMut<Time<Fixed>>,
+) {
+    for event in events.read() {
+        match Snapshot::load(&event.path) {
+            Ok(snapshot) => {
+                // run the clock forward when it is behind, saved timestamps can't lie in the future
+                if time.elapsed() < snapshot.elapsed {
+                    time.advance_to(snapshot.elapsed);
+                }
+                commands.insert_resource(PendingSnapshot(snapshot));
+            }
+            Err(error) => error!("couldn't load snapshot {}: {}", event.path.display(), error),
+        }
+    }
+}
+
+fn restore_snapshot(
+    mut commands: Commands,
+    pending: Res<PendingSnapshot>,
+    mut score: ResMut<Score>,
+    mut spawner: ResMut<SpawnGenerator>,
+    mut tick: ResMut<SimulationTick>,
+) {
+    *score = pending.0.score;
+    spawner.rng = pending.0.spawner.clone();
+    tick.0 = pending.0.tick;
+    commands.remove_resource::<PendingSnapshot>();
+}
+
+fn quicksave(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut save: EventWriter<SaveSnapshot>,
+    mut load: EventWriter<LoadSnapshot>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        save.send(SaveSnapshot {
+            path: PathBuf::from(QUICKSAVE_PATH),
+        });
+    }
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        load.send(LoadSnapshot {
+            path: PathBuf::from(QUICKSAVE_PATH),
+        });
+    }
+}
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveSnapshot>();
+        app.add_event::<LoadSnapshot>();
+        app.init_resource::<RestoredEntities>();
+        // the ship is gone once the game is over, nothing left worth saving
+        app.add_systems(
+            Update,
+            (save_snapshot, load_snapshot)
+                .in_set(InGameSet::MenuInput)
+                .run_if(in_state(GameState::InGame).or(in_state(GameState::Paused))),
+        );
+        // once every plugin has restored its part, some of them wait on each other's commands
+        app.add_systems(
+            PostUpdate,
+            (restore_snapshot).run_if(resource_exists::<PendingSnapshot>),
+        );
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(Update, (quicksave).in_set(InGameSet::MenuInput));
+        }
+    }
+}
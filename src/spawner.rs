@@ -1,13 +1,248 @@
+use crate::grid::Grid;
 use bevy::prelude::*;
 use bevy_turborand::prelude::*;
+use serde::Deserialize;
+
+// where new asteroids may appear and how they start moving
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct SpawnPolicy {
+    // no spawn closer than this to any ship, measured across the wrap
+    pub safe_radius: f32,
+    // 0 leaves the drift random, 1 turns it fully sideways to the nearest ship
+    pub tangential_bias: f32,
+    // rolls before giving up, the asteroid then waits for a later tick
+    pub attempts: u32,
+}
 
 #[derive(Resource)]
 pub struct SpawnGenerator {
     pub rng: RngComponent,
 }
 
+impl SpawnGenerator {
+    // index picked with a chance proportional to its weight, one roll either way
+    pub fn weighted(&mut self, weights: &[f32]) -> usize {
+        let mut roll = self.rng.f32() * weights.iter().sum::<f32>();
+        for (index, weight) in weights.iter().enumerate() {
+            if roll < *weight {
+                return index;
+            }
+            roll -= weight;
+        }
+        // rounding can leave the roll just past the last weight, which may be one never picked
+        weights
+            .iter()
+            .rposition(|weight| *weight > 0.0)
+            .unwrap_or(0)
+    }
+
+    // between the screen edge and the wrap, on either side of it
+    fn beyond(&mut self, half: f32, extends: f32) -> f32 {
+        let dist = self.rng.f32_normalized() * extends;
+        if dist < 0.0 {
+            dist - half
+        } else {
+            dist + half
+        }
+    }
+
+    // anywhere in the band around the screen, evenly over its area. the strips left and right
+    // run the full height, the ones above and below only span the screen width
+    pub fn offscreen(&mut self, grid: &Grid) -> Vec2 {
+        let sides = grid.extends * (grid.height_half + grid.extends);
+        let ends = grid.extends * grid.width_half;
+        if self.rng.f32() * (sides + ends) < sides {
+            let x = self.beyond(grid.width_half, grid.extends);
+            let y = self.rng.f32_normalized() * (grid.height_half + grid.extends);
+            Vec2::new(x, y)
+        } else {
+            let x = self.rng.f32_normalized() * grid.width_half;
+            let y = self.beyond(grid.height_half, grid.extends);
+            Vec2::new(x, y)
+        }
+    }
+
+    // rerolls points inside the safe radius of a ship, None when every attempt lands too close
+    pub fn safe_position(
+        &mut self,
+        policy: &SpawnPolicy,
+        grid: &Grid,
+        ships: &[Vec2],
+    ) -> Option<Vec2> {
+        (0..policy.attempts.max(1))
+            .map(|_| self.offscreen(grid))
+            .find(|&position| {
+                nearest_ship(grid, position, ships)
+                    .is_none_or(|ship| ship.length() >= policy.safe_radius)
+            })
+    }
+
+    // random drift up to speed on each axis, turned sideways to the nearest ship by the bias
+    // so it doesn't start out heading straight at the player
+    pub fn drift(
+        &mut self,
+        policy: &SpawnPolicy,
+        grid: &Grid,
+        position: Vec2,
+        ships: &[Vec2],
+        speed: f32,
+    ) -> Vec2 {
+        let velocity = Vec2::new(
+            self.rng.f32_normalized() * speed,
+            self.rng.f32_normalized() * speed,
+        );
+        let Some(toward) =
+            nearest_ship(grid, position, ships).and_then(|ship| ship.try_normalize())
+        else {
+            return velocity;
+        };
+        // keep whichever way round it was already going
+        let tangent = if velocity.dot(toward.perp()) < 0.0 {
+            -toward.perp()
+        } else {
+            toward.perp()
+        };
+        // only the direction turns, the speed stays what was rolled
+        let speed = velocity.length();
+        velocity
+            .lerp(tangent * speed, policy.tangential_bias.clamp(0.0, 1.0))
+            .normalize_or_zero()
+            * speed
+    }
+}
+
+// shortest vector from position to the closest ship, across the wrap
+fn nearest_ship(grid: &Grid, position: Vec2, ships: &[Vec2]) -> Option<Vec2> {
+    ships
+        .iter()
+        .map(|ship| grid.wrapped_delta(position, *ship))
+        .min_by(|a, b| a.length().total_cmp(&b.length()))
+}
+
 pub fn load_spawner(mut commands: Commands, mut global_rng: ResMut<GlobalRng>) {
     commands.insert_resource(SpawnGenerator {
         rng: RngComponent::from(&mut global_rng),
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_grid() -> Grid {
+        Grid {
+            size: 100.0,
+            extends: 0.5,
+            height_half: 3.0,
+            width_half: 5.0,
+        }
+    }
+
+    fn seeded(seed: u64) -> SpawnGenerator {
+        SpawnGenerator {
+            rng: RngComponent::with_seed(seed),
+        }
+    }
+
+    fn policy(tangential_bias: f32) -> SpawnPolicy {
+        SpawnPolicy {
+            safe_radius: 2.0,
+            tangential_bias,
+            attempts: 16,
+        }
+    }
+
+    #[test]
+    fn offscreen_covers_the_whole_band() {
+        let grid = test_grid();
+        let mut spawner = seeded(5);
+        let (mut beside, mut above) = (false, false);
+        for _ in 0..500 {
+            let position = spawner.offscreen(&grid);
+            let outside_x = position.x.abs() >= grid.width_half;
+            let outside_y = position.y.abs() >= grid.height_half;
+            assert!(outside_x || outside_y);
+            assert!(position.x.abs() <= grid.width_half + grid.extends);
+            assert!(position.y.abs() <= grid.height_half + grid.extends);
+            beside |= outside_x && position.y.abs() < grid.height_half - 1.0;
+            above |= outside_y && position.x.abs() < grid.width_half - 1.0;
+        }
+        assert!(beside && above, "never spawned along the edges, only near the corners");
+    }
+
+    #[test]
+    fn keeps_clear_of_ships() {
+        let grid = test_grid();
+        // right next to the band, plenty of rolls land inside the radius
+        let ships = [Vec2::new(4.0, 0.0)];
+        let mut placed = 0;
+        for seed in 0..200 {
+            let Some(position) = seeded(seed).safe_position(&policy(0.0), &grid, &ships) else {
+                continue;
+            };
+            placed += 1;
+            for ship in ships {
+                assert!(grid.wrapped_delta(position, ship).length() >= 2.0);
+            }
+        }
+        assert!(placed > 0);
+    }
+
+    #[test]
+    fn gives_up_when_nothing_is_clear() {
+        let grid = test_grid();
+        // wider than the whole world
+        let policy = SpawnPolicy {
+            safe_radius: 20.0,
+            ..policy(0.0)
+        };
+        for seed in 0..20 {
+            assert_eq!(seeded(seed).safe_position(&policy, &grid, &[Vec2::ZERO]), None);
+        }
+        assert!(seeded(0).safe_position(&policy, &grid, &[]).is_some());
+    }
+
+    #[test]
+    fn full_bias_drifts_sideways() {
+        let grid = test_grid();
+        let ships = [Vec2::ZERO];
+        for seed in 0..50 {
+            let position = Vec2::new(5.2, 3.2);
+            let velocity = seeded(seed).drift(&policy(1.0), &grid, position, &ships, 2.0);
+            let raw = seeded(seed).drift(&policy(0.0), &grid, position, &ships, 2.0);
+            let toward = grid.wrapped_delta(position, Vec2::ZERO).normalize();
+            assert!(velocity.dot(toward).abs() < 1e-4);
+            assert!((velocity.length() - raw.length()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn partial_bias_keeps_the_speed() {
+        let grid = test_grid();
+        let ships = [Vec2::ZERO];
+        for seed in 0..50 {
+            let position = Vec2::new(5.2, 3.2);
+            let velocity = seeded(seed).drift(&policy(0.5), &grid, position, &ships, 2.0);
+            let raw = seeded(seed).drift(&policy(0.0), &grid, position, &ships, 2.0);
+            assert!((velocity.length() - raw.length()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn no_bias_or_no_ships_leaves_drift_alone() {
+        let grid = test_grid();
+        let position = Vec2::new(5.2, 3.2);
+        let expected = seeded(7).drift(&policy(0.0), &grid, position, &[Vec2::ZERO], 2.0);
+        let unbiased = seeded(7).drift(&policy(1.0), &grid, position, &[], 2.0);
+        assert_eq!(expected, unbiased);
+    }
+
+    #[test]
+    fn zero_weights_are_never_picked() {
+        let mut spawner = seeded(3);
+        for _ in 0..500 {
+            assert_ne!(spawner.weighted(&[1.0, 0.0, 2.0]), 1);
+            assert_ne!(spawner.weighted(&[1.0, 2.0, 0.0]), 2);
+        }
+    }
+}
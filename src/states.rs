@@ -1,4 +1,5 @@
 use crate::schedule::InGameSet;
+use crate::Headless;
 use bevy::prelude::*;
 
 #[derive(Component, Debug)]
@@ -6,12 +7,18 @@ struct RestartMessage;
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum GameState {
+    // waits for configs and assets, so the first tick doesn't depend on how fast they load
     #[default]
+    Loading,
     InGame,
     Paused,
     GameOver,
 }
 
+pub fn finish_loading(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::InGame);
+}
+
 fn pause_system(
     mut next_state: ResMut<NextState<GameState>>,
     state: Res<State<GameState>>,
@@ -45,6 +52,15 @@ fn restart_game(
     }
 }
 
+// fixed time only runs while playing, so elapsed times line up with the tick count
+fn pause_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+fn unpause_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
+
 fn spawn_restart_message(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -82,11 +98,17 @@ pub struct StatePlugin;
 impl Plugin for StatePlugin {
     fn build(&self, app: &mut App) {
         app.init_state::<GameState>();
-        app.add_systems(
-            Update,
-            (pause_system, restart_game).in_set(InGameSet::MenuInput),
-        );
-        app.add_systems(OnEnter(GameState::GameOver), spawn_restart_message);
-        app.add_systems(OnExit(GameState::GameOver), despawn_restart_message);
+        app.add_systems(OnEnter(GameState::Loading), pause_time);
+        app.add_systems(OnEnter(GameState::InGame), unpause_time);
+        app.add_systems(OnExit(GameState::InGame), pause_time);
+        // headless runs drive the state themselves
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(
+                Update,
+                (pause_system, restart_game).in_set(InGameSet::MenuInput),
+            );
+            app.add_systems(OnEnter(GameState::GameOver), spawn_restart_message);
+            app.add_systems(OnExit(GameState::GameOver), despawn_restart_message);
+        }
     }
 }
@@ -0,0 +1,236 @@
+use crate::{
+    asteroid::{spawn_asteroid_random, Asteroid, SpawnAsteroid},
+    bodies::TimeStamp,
+    schedule::InGameSet,
+    score::Score,
+    snapshot::PendingSnapshot,
+    spawner::SpawnGenerator,
+    GameState, Headless,
+};
+use bevy::prelude::*;
+use bevy_common_assets::ron::RonAssetPlugin;
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Deserialize, Clone, Copy)]
+struct SizeMix {
+    scale: (f32, f32),
+    // relative chance of an asteroid of the wave being this size
+    weight: f32,
+}
+
+#[derive(Deserialize, Clone)]
+struct WaveConfig {
+    count: u32,
+    sizes: Vec<SizeMix>,
+    // scales the random drift every asteroid starts with
+    speed: f32,
+}
+
+#[derive(Deserialize, Asset, Clone, TypePath)]
+pub(crate) struct WavesConfig {
+    // ms between two asteroids of the same wave
+    interval: u64,
+    // ms after a wave is cleared before the next one starts
+    delay: u64,
+    // ms the banner stays up
+    banner: u64,
+    waves: Vec<WaveConfig>,
+    // past the last wave it repeats, adding this much every time
+    count_growth: u32,
+    speed_growth: f32,
+}
+
+impl WavesConfig {
+    // numbered from 1, returns the count and speed after growth
+    fn wave(&self, number: u16) -> Option<(&WaveConfig, u32, f32)> {
+        let index = usize::from(number.max(1)) - 1;
+        let last = self.waves.len().checked_sub(1)?;
+        let wave = &self.waves[index.min(last)];
+        let extra = index.saturating_sub(last);
+        Some((
+            wave,
+            wave.count + self.count_growth * extra as u32,
+            wave.speed + self.speed_growth * extra as f32,
+        ))
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct WavesConfigHandle {
+    config: Handle<WavesConfig>,
+}
+
+// the wave in progress and how far along it is
+#[derive(Resource, Default, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Wave {
+    // 0 until the first wave starts
+    pub number: u16,
+    // asteroids of the wave still to come
+    pub to_spawn: u32,
+    last_spawn: TimeStamp,
+    started: TimeStamp,
+    // when the last asteroid went, the next wave follows delay ms later
+    cleared: Option<TimeStamp>,
+}
+
+#[derive(Component)]
+struct WaveBanner;
+
+fn load_config(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let config = asset_server.load("a.waves.ron");
+    commands.insert_resource(WavesConfigHandle { config });
+}
+
+pub fn waves_loaded(
+    configs: Res<Assets<WavesConfig>>,
+    config_handle: Res<WavesConfigHandle>,
+) -> bool {
+    configs.contains(config_handle.config.id())
+}
+
+// starts the next wave once the screen is clear, then feeds its asteroids in one at a time
+fn direct_waves(
+    time: Res<Time>,
+    mut wave: ResMut<Wave>,
+    mut score: ResMut<Score>,
+    mut spawner: ResMut<SpawnGenerator>,
+    mut spawns: EventWriter<SpawnAsteroid>,
+    asteroids: Query<(), With<Asteroid>>,
+    configs: Res<Assets<WavesConfig>>,
+    config_handle: Res<WavesConfigHandle>,
+) {
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
+    };
+    let now = time.elapsed();
+    if wave.to_spawn > 0 {
+        if now - wave.last_spawn.0 < Duration::from_millis(config.interval) {
+            return;
+        }
+        let Some((wave_config, _, speed)) = config.wave(wave.number) else {
+            return;
+        };
+        let weights: Vec<f32> = wave_config.sizes.iter().map(|size| size.weight).collect();
+        let Some(size) = wave_config.sizes.get(spawner.weighted(&weights)) else {
+            return;
+        };
+        let scale = size.scale.0 + spawner.rng.f32() * (size.scale.1 - size.scale.0);
+        spawns.send(SpawnAsteroid { scale, speed });
+        wave.to_spawn -= 1;
+        wave.last_spawn = TimeStamp(now);
+        return;
+    }
+    if !asteroids.is_empty() {
+        return;
+    }
+    match wave.cleared {
+        None => wave.cleared = Some(TimeStamp(now)),
+        Some(cleared) if now - cleared.0 >= Duration::from_millis(config.delay) => {
+            let number = wave.number + 1;
+            if let Some((_, count, _)) = config.wave(number) {
+                *wave = Wave {
+                    number,
+                    to_spawn: count,
+                    // the first asteroid comes straight away
+                    last_spawn: TimeStamp(Duration::ZERO),
+                    started: TimeStamp(now),
+                    cleared: None,
+                };
+                score.wave = number;
+            }
+        }
+        _ => (),
+    }
+}
+
+fn restore_wave(pending: Res<PendingSnapshot>, mut wave: ResMut<Wave>, time: Res<Time<Fixed>>) {
+    let saved = pending.0.wave;
+    *wave = Wave {
+        last_spawn: pending.restamp(saved.last_spawn, time.elapsed()),
+        started: pending.restamp(saved.started, time.elapsed()),
+        cleared: saved
+            .cleared
+            .map(|cleared| pending.restamp(cleared, time.elapsed())),
+        ..saved
+    };
+}
+
+fn reset_wave(mut wave: ResMut<Wave>) {
+    *wave = Wave::default();
+}
+
+fn spawn_banner(mut commands: Commands, asset_server: Res<AssetServer>, window: Query<&Window>) {
+    if let Ok(window) = window.get_single() {
+        let text_height = window.resolution.height() / 4.0;
+
+        let font = asset_server.load("fonts/FiraMono-Medium.ttf");
+        let text_font = TextFont {
+            font,
+            font_size: 48.0,
+            ..default()
+        };
+
+        commands.spawn((
+            WaveBanner,
+            Text2d::new(""),
+            text_font,
+            TextLayout::new_with_justify(JustifyText::Center),
+            Transform::from_translation(Vec3::new(0.0, text_height, 0.0)),
+        ));
+    }
+}
+
+// up for a while at the start of every wave, timed on the fixed clock the wave started on
+fn update_banner(
+    wave: Res<Wave>,
+    time: Res<Time<Fixed>>,
+    mut banners: Query<&mut Text2d, With<WaveBanner>>,
+    configs: Res<Assets<WavesConfig>>,
+    config_handle: Res<WavesConfigHandle>,
+) {
+    let Some(config) = configs.get(config_handle.config.id()) else {
+        return;
+    };
+    if let Ok(mut banner) = banners.get_single_mut() {
+        let showing = wave.number > 0
+            && time.elapsed().saturating_sub(wave.started.0) < Duration::from_millis(config.banner);
+        let text = if showing {
+            format!("Wave {}", wave.number)
+        } else {
+            String::new()
+        };
+        if banner.0 != text {
+            banner.0 = text;
+        }
+    }
+}
+
+pub struct WavePlugin;
+
+impl Plugin for WavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<WavesConfig>::new(&["waves.ron"]));
+        app.init_resource::<Wave>();
+        app.add_systems(Startup, load_config);
+        app.add_systems(
+            Update,
+            (restore_wave)
+                .in_set(InGameSet::LoadEntities)
+                .run_if(resource_exists::<PendingSnapshot>),
+        );
+        // at the end of the tick with the other spawns
+        app.add_systems(
+            FixedUpdate,
+            (direct_waves)
+                .before(spawn_asteroid_random)
+                .in_set(InGameSet::CollisionReaction),
+        );
+        app.add_systems(OnExit(GameState::GameOver), reset_wave);
+        if !app.world().contains_resource::<Headless>() {
+            app.add_systems(Startup, spawn_banner);
+            app.add_systems(Update, (update_banner).in_set(InGameSet::RenderSetup));
+        }
+    }
+}
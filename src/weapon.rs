@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy_common_assets::ron::RonAssetPlugin;
+use serde::Deserialize;
+
+// switching cycles through them in this order
+const WEAPON_PATHS: [&str; 5] = [
+    "blaster.weapon.ron",
+    "spread.weapon.ron",
+    "rapid.weapon.ron",
+    "piercing.weapon.ron",
+    "beam.weapon.ron",
+];
+
+#[derive(Deserialize, Asset, Clone, TypePath)]
+pub(crate) struct WeaponConfig {
+    pub(crate) name: String,
+    pub(crate) fire_delay: u64,
+    // bullets per shot, fanned out evenly over spread radians
+    pub(crate) bullets: u32,
+    pub(crate) spread: f32,
+    // ms the trigger has to be held before the shot goes off
+    pub(crate) charge: u64,
+    pub(crate) speed: f32,
+    pub(crate) lifetime: u64,
+    pub(crate) size: f32,
+    pub(crate) color: (f32, f32, f32),
+    pub(crate) density: f32,
+    pub(crate) damage: f32,
+    // asteroids a bullet passes through before it is used up
+    pub(crate) pierce: u32,
+}
+
+#[derive(Resource)]
+pub(crate) struct Weapons {
+    configs: Vec<Handle<WeaponConfig>>,
+}
+
+impl Weapons {
+    pub(crate) fn len(&self) -> usize {
+        self.configs.len()
+    }
+
+    pub(crate) fn get<'a>(
+        &self,
+        configs: &'a Assets<WeaponConfig>,
+        index: usize,
+    ) -> Option<&'a WeaponConfig> {
+        configs.get(self.configs.get(index)?.id())
+    }
+}
+
+fn load_config(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let configs = WEAPON_PATHS
+        .iter()
+        .map(|path| asset_server.load(*path))
+        .collect();
+    commands.insert_resource(Weapons { configs });
+}
+
+pub fn weapons_loaded(weapons: Option<Res<Weapons>>, configs: Res<Assets<WeaponConfig>>) -> bool {
+    weapons.is_some_and(|weapons| {
+        weapons
+            .configs
+            .iter()
+            .all(|config| configs.contains(config.id()))
+    })
+}
+
+pub struct WeaponPlugin;
+
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<WeaponConfig>::new(&["weapon.ron"]));
+        app.add_systems(Startup, load_config);
+    }
+}
@@ -0,0 +1,232 @@
+use asteroids::*;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const MAX_TICKS: u64 = 60 * 120;
+
+fn headless_app(replay: Option<PathBuf>) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.add_plugins(AsteroidsGamePlugin {
+        headless: Some(Headless {
+            width: 1280.0,
+            height: 720.0,
+        }),
+        replay,
+        ..default()
+    });
+    // one tick per update, however fast the test machine is
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / 60.0,
+    )));
+    app
+}
+
+fn state(app: &App) -> GameState {
+    *app.world().resource::<State<GameState>>().get()
+}
+
+fn tick(app: &App) -> u64 {
+    app.world().resource::<SimulationTick>().0
+}
+
+// steps until the game is over or the tick budget runs out, calling drive before every update
+fn run(app: &mut App, max_ticks: u64, mut drive: impl FnMut(&mut World, u64)) {
+    // configs load on the io threads, give them a moment
+    let mut frames = 0;
+    while state(app) == GameState::Loading {
+        app.update();
+        frames += 1;
+        assert!(frames < 1000, "game never finished loading");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    while state(app) != GameState::GameOver && tick(app) < max_ticks {
+        let tick = tick(app);
+        drive(app.world_mut(), tick);
+        app.update();
+    }
+    // a game over on the last tick only lands on the next update, which doesn't tick any more
+    if let NextState::Pending(_) = app.world().resource::<NextState<GameState>>() {
+        app.update();
+    }
+}
+
+// after this the pilot stops defending itself and rams asteroids until it runs out of lives
+const KAMIKAZE_TICK: u64 = 60 * 40;
+
+// turn in circles, thrust in bursts, keep firing, jump, shield, switch weapons and launch missiles now and then
+fn scripted_pilot(world: &mut World, tick: u64) {
+    let mut controllers = world.query_filtered::<Entity, With<PlayerController>>();
+    let Some(controller) = controllers.iter(world).next() else {
+        return;
+    };
+    if tick == KAMIKAZE_TICK {
+        world.send_event(Shoot {
+            controller,
+            held: false,
+        });
+    }
+    if tick >= KAMIKAZE_TICK {
+        ram_nearest_asteroid(world, controller);
+        return;
+    }
+    let thrust = if tick % 90 < 30 { 1.0 } else { 0.0 };
+    let turn = if tick % 240 < 120 { 1.0 } else { -1.0 };
+    world.send_event(Accelerate {
+        controller,
+        direction: Vec2::new(0.0, thrust),
+    });
+    world.send_event(AccelerateAngular {
+        controller,
+        direction: turn,
+    });
+    world.send_event(Shoot {
+        controller,
+        held: true,
+    });
+    if tick % 600 == 300 {
+        world.send_event(Hyperspace { controller });
+    }
+    let shield_phase = tick % 300;
+    if shield_phase == 0 || shield_phase == 60 {
+        world.send_event(RaiseShield {
+            controller,
+            held: shield_phase == 0,
+        });
+    }
+    if tick % 900 == 450 {
+        world.send_event(SwitchWeapon { controller });
+    }
+    if tick % 400 == 200 {
+        world.send_event(LaunchMissile { controller });
+    }
+}
+
+// full thrust, nose turned onto the closest asteroid. ignores the wrap, it gets there either way
+fn ram_nearest_asteroid(world: &mut World, controller: Entity) {
+    let mut ships = world.query_filtered::<(&Position, &Rotation), With<Ship>>();
+    let Some((ship, rotation)) = ships.iter(world).next().map(|(p, r)| (p.0, r.0)) else {
+        return;
+    };
+    let mut asteroids = world.query_filtered::<&Position, With<Asteroid>>();
+    let nearest = asteroids
+        .iter(world)
+        .map(|position| position.0 - ship)
+        .min_by(|a, b| a.length().total_cmp(&b.length()));
+    let turn = nearest.map_or(0.0, |delta| {
+        (Rot2::radians(rotation) * Vec2::Y).angle_to(delta).signum()
+    });
+    world.send_event(Accelerate {
+        controller,
+        direction: Vec2::new(0.0, 1.0),
+    });
+    world.send_event(AccelerateAngular {
+        controller,
+        direction: turn,
+    });
+}
+
+#[test]
+fn replay_reproduces_the_game() {
+    let mut recorded = headless_app(None);
+    run(&mut recorded, MAX_TICKS, scripted_pilot);
+    let recording = recorded.world().resource::<InputRecording>().clone();
+    let recorded_score = *recorded.world().resource::<Score>();
+    let recorded_tick = tick(&recorded);
+    assert!(!recording.events.is_empty());
+    // otherwise comparing game-over ticks below compares nothing
+    assert!(recording.game_over.is_some(), "the pilot never ran out of lives");
+    assert!(recorded_score.score > 0, "the pilot never hit anything");
+    let pool = recorded.world().resource::<BulletPool>().stats();
+    assert!(pool.reused > 0, "no shot came out of the pool");
+
+    let path = std::env::temp_dir().join(format!("asteroids-{}.replay.ron", std::process::id()));
+    recording.save(&path).unwrap();
+    assert_eq!(InputRecording::load(&path).unwrap(), recording);
+
+    let mut replayed = headless_app(Some(path.clone()));
+    run(&mut replayed, recorded_tick, |_, _| ());
+    let replayed_recording = replayed.world().resource::<InputRecording>().clone();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(tick(&replayed), recorded_tick);
+    assert_eq!(state(&replayed), state(&recorded));
+    assert_eq!(*replayed.world().resource::<Score>(), recorded_score);
+    assert_eq!(replayed_recording.game_over, recording.game_over);
+    assert_eq!(replayed_recording.events, recording.events);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut recording = InputRecording::new(1024, 60.0);
+    recording.version = REPLAY_VERSION + 1;
+    let path = std::env::temp_dir().join(format!("asteroids-{}.old.ron", std::process::id()));
+    recording.save(&path).unwrap();
+    let loaded = InputRecording::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(loaded.is_err());
+}
+
+// a bad --replay argument is logged and the game starts as usual
+#[test]
+fn missing_replay_plays_a_normal_game() {
+    let path = std::env::temp_dir().join(format!("asteroids-{}.missing.ron", std::process::id()));
+    let mut app = headless_app(Some(path));
+    run(&mut app, 60, |_, _| ());
+    assert_eq!(state(&app), GameState::InGame);
+    assert_eq!(tick(&app), 60);
+}
+
+// a second game after the first one ended, the replay has to start it on the same tick
+#[test]
+fn replay_follows_a_restart() {
+    let kamikaze = |world: &mut World, _| {
+        let mut controllers = world.query_filtered::<Entity, With<PlayerController>>();
+        if let Some(controller) = controllers.iter(world).next() {
+            ram_nearest_asteroid(world, controller);
+        }
+    };
+    let mut recorded = headless_app(None);
+    run(&mut recorded, MAX_TICKS, kamikaze);
+    assert_eq!(state(&recorded), GameState::GameOver);
+    // sit on the game over screen for a while before starting again
+    for _ in 0..30 {
+        recorded.update();
+    }
+    recorded
+        .world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::InGame);
+    recorded.update();
+    let mut controllers = recorded
+        .world_mut()
+        .query_filtered::<Entity, With<PlayerController>>();
+    assert_eq!(controllers.iter(recorded.world()).count(), 1);
+    run(&mut recorded, MAX_TICKS * 2, kamikaze);
+    let recording = recorded.world().resource::<InputRecording>().clone();
+    let recorded_tick = tick(&recorded);
+    assert_eq!(recording.restarts.len(), 1);
+    assert!(recording.game_over.is_some(), "the second game never ended");
+
+    let path = std::env::temp_dir().join(format!("asteroids-{}.restart.ron", std::process::id()));
+    recording.save(&path).unwrap();
+    let mut replayed = headless_app(Some(path.clone()));
+    run(&mut replayed, recorded_tick, |_, _| ());
+    let mut frames = 0;
+    while state(&replayed) == GameState::GameOver {
+        replayed.update();
+        frames += 1;
+        assert!(frames < 1000, "the replay never restarted");
+    }
+    run(&mut replayed, recorded_tick, |_, _| ());
+    let replayed_recording = replayed.world().resource::<InputRecording>().clone();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(tick(&replayed), recorded_tick);
+    assert_eq!(*replayed.world().resource::<Score>(), *recorded.world().resource::<Score>());
+    assert_eq!(replayed_recording.restarts, recording.restarts);
+    assert_eq!(replayed_recording.game_over, recording.game_over);
+    assert_eq!(replayed_recording.events, recording.events);
+}
@@ -0,0 +1,174 @@
+use asteroids::*;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use std::time::Duration;
+
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.add_plugins(AsteroidsGamePlugin {
+        headless: Some(Headless {
+            width: 1280.0,
+            height: 720.0,
+        }),
+        ..default()
+    });
+    // one tick per update, however fast the test machine is
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / 60.0,
+    )));
+    app
+}
+
+fn state(app: &App) -> GameState {
+    *app.world().resource::<State<GameState>>().get()
+}
+
+fn tick(app: &App) -> u64 {
+    app.world().resource::<SimulationTick>().0
+}
+
+fn controller(world: &mut World) -> Entity {
+    let mut controllers = world.query_filtered::<Entity, With<PlayerController>>();
+    controllers.iter(world).next().unwrap()
+}
+
+// fly around firing both guns, then let go of everything so the rest only depends on the world
+fn play(app: &mut App, ticks: u64) {
+    let mut frames = 0;
+    while state(app) == GameState::Loading {
+        app.update();
+        frames += 1;
+        assert!(frames < 1000, "game never finished loading");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let controller = controller(app.world_mut());
+    let world = app.world_mut();
+    world.send_event(Shoot {
+        controller,
+        held: true,
+    });
+    world.send_event(AccelerateAngular {
+        controller,
+        direction: 1.0,
+    });
+    for tick in 0..ticks {
+        let thrust = if tick % 90 < 30 { 1.0 } else { 0.0 };
+        app.world_mut().send_event(Accelerate {
+            controller,
+            direction: Vec2::new(0.0, thrust),
+        });
+        if tick % 120 == 60 {
+            app.world_mut().send_event(LaunchMissile { controller });
+        }
+        app.update();
+    }
+    let world = app.world_mut();
+    world.send_event(Shoot {
+        controller,
+        held: false,
+    });
+    world.send_event(Accelerate {
+        controller,
+        direction: Vec2::ZERO,
+    });
+    world.send_event(AccelerateAngular {
+        controller,
+        direction: 0.0,
+    });
+    app.update();
+}
+
+fn idle(app: &mut App, ticks: u64) {
+    for _ in 0..ticks {
+        app.update();
+    }
+}
+
+// everything the simulation carries forward, sorted so entity order doesn't matter
+#[derive(Debug, PartialEq)]
+struct Digest {
+    tick: u64,
+    score: Score,
+    wave: (u16, u32),
+    bodies: Vec<String>,
+}
+
+fn digest(world: &mut World) -> Digest {
+    let mut bodies = Vec::new();
+    let mut ships = world.query_filtered::<(
+        &Position,
+        &Velocity,
+        &Rotation,
+        &Magazine,
+        &Shield,
+    ), With<Ship>>();
+    for (position, velocity, rotation, magazine, shield) in ships.iter(world) {
+        bodies.push(format!(
+            "ship {:?} {:?} {:?} rounds {} shield {}",
+            position.0, velocity.0, rotation.0, magazine.rounds, shield.energy
+        ));
+    }
+    let mut asteroids =
+        world.query_filtered::<(&Position, &Velocity, &Rotation, &Health), With<Asteroid>>();
+    for (position, velocity, rotation, health) in asteroids.iter(world) {
+        bodies.push(format!(
+            "asteroid {:?} {:?} {:?} health {}",
+            position.0, velocity.0, rotation.0, health.current
+        ));
+    }
+    let mut bullets = world.query::<(&Position, &Velocity, &Bullet)>();
+    for (position, velocity, bullet) in bullets.iter(world) {
+        bodies.push(format!(
+            "bullet {:?} {:?} pierce {}",
+            position.0, velocity.0, bullet.pierce
+        ));
+    }
+    let mut missiles = world.query::<(&Position, &Velocity, &Rotation, &Missile)>();
+    for (position, velocity, rotation, missile) in missiles.iter(world) {
+        bodies.push(format!(
+            "missile {:?} {:?} {:?} fuel {}",
+            position.0, velocity.0, rotation.0, missile.fuel
+        ));
+    }
+    let mut lives = world.query::<&Lives>();
+    for lives in lives.iter(world) {
+        bodies.push(format!("lives {}", lives.remaining));
+    }
+    bodies.sort();
+    let wave = world.resource::<Wave>();
+    Digest {
+        tick: world.resource::<SimulationTick>().0,
+        score: *world.resource::<Score>(),
+        wave: (wave.number, wave.to_spawn),
+        bodies,
+    }
+}
+
+// save, play on, load, and the world is back where it was and goes on the same way
+#[test]
+fn load_restores_the_saved_world() {
+    let mut app = headless_app();
+    play(&mut app, 60 * 8);
+    assert_eq!(state(&app), GameState::InGame, "the ship didn't survive until the save");
+
+    let path = std::env::temp_dir().join(format!("asteroids-{}.snapshot.ron", std::process::id()));
+    app.world_mut().send_event(SaveSnapshot { path: path.clone() });
+    app.update();
+    let saved = digest(app.world_mut());
+    assert!(saved.bodies.iter().any(|body| body.starts_with("bullet")));
+    assert!(saved.bodies.iter().any(|body| body.starts_with("missile")));
+
+    idle(&mut app, 120);
+    let played_on = digest(app.world_mut());
+    assert_ne!(played_on, saved);
+
+    app.world_mut().send_event(LoadSnapshot { path: path.clone() });
+    app.update();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(digest(app.world_mut()), saved);
+    assert_eq!(tick(&app), saved.tick);
+
+    idle(&mut app, 120);
+    assert_eq!(digest(app.world_mut()), played_on);
+}